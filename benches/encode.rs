@@ -0,0 +1,66 @@
+use criterion::{Criterion, Throughput, black_box, criterion_group, criterion_main};
+use geronimo_captcha::image_utils::encode_image;
+use geronimo_captcha::{JpegSubsampling, SpriteFormat};
+use image::{DynamicImage, Rgba, RgbaImage};
+
+/// Gradient with a checker overlay, so encoders have some detail to chew on.
+fn make_sprite(side: u32) -> DynamicImage {
+    let img = RgbaImage::from_fn(side, side, |x, y| {
+        let checker = if (x / 8 + y / 8) % 2 == 0 { 0 } else { 48 };
+        Rgba([
+            (x * 255 / side) as u8,
+            (y * 255 / side) as u8,
+            128u8.saturating_add(checker),
+            255,
+        ])
+    });
+
+    DynamicImage::ImageRgba8(img)
+}
+
+fn jpeg(quality: u8) -> SpriteFormat {
+    SpriteFormat::Jpeg {
+        quality,
+        progressive: false,
+        chroma_subsampling: JpegSubsampling::Yuv420,
+    }
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode_image");
+
+    let formats = [
+        ("jpeg_q40", jpeg(40)),
+        ("jpeg_q70", jpeg(70)),
+        ("jpeg_q90", jpeg(90)),
+        (
+            "webp_q70",
+            SpriteFormat::Webp {
+                quality: 70,
+                method: 4,
+            },
+        ),
+        (
+            "webp_lossless",
+            SpriteFormat::WebpLossless {
+                compression_level: 6,
+            },
+        ),
+    ];
+
+    for side in [100u32, 300] {
+        let sprite = make_sprite(side);
+        group.throughput(Throughput::Elements(1));
+
+        for (name, fmt) in &formats {
+            group.bench_function(format!("{side}x{side}/{name}"), |b| {
+                b.iter(|| black_box(encode_image(&sprite, fmt).unwrap()));
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_encode);
+criterion_main!(benches);
@@ -1,22 +1,34 @@
 use criterion::{BatchSize, Criterion, Throughput, black_box, criterion_group, criterion_main};
+use geronimo_captcha::image_utils::{rotate_image, watermark_with_noise};
 use geronimo_captcha::{
-    CaptchaManager, GenerationOptions, NoiseOptions, SpriteBinary, SpriteFormat, SpriteUri,
+    CaptchaManager, ChallengeType, GenerationOptions, JpegSubsampling, MAX_ANGLE_TOLERANCE_DEG,
+    NoiseLayer, NoiseOptions, NoisePattern, SpriteBinary, SpriteBufferPool, SpriteFormat,
+    SpriteUri,
 };
+use image::{DynamicImage, Rgba, RgbaImage};
 
 fn make_mgr(cell: u32, q: u8, ttl: u64) -> CaptchaManager {
-    make_mgr_with(cell, SpriteFormat::Jpeg { quality: q }, ttl)
+    make_mgr_with(
+        cell,
+        SpriteFormat::Jpeg {
+            quality: q,
+            progressive: false,
+            chroma_subsampling: JpegSubsampling::Yuv420,
+        },
+        ttl,
+    )
 }
 
 fn make_mgr_with(cell: u32, format: SpriteFormat, ttl: u64) -> CaptchaManager {
     let opts = GenerationOptions {
         cell_size: cell,
         sprite_format: format,
-        limits: None,
+        ..Default::default()
     };
     let noise = NoiseOptions::default();
     let secret = String::from("bench-secret");
 
-    CaptchaManager::new(secret, ttl, noise, None, opts)
+    CaptchaManager::new(secret, ttl, noise, None, opts).expect("valid options")
 }
 
 fn bench_verify(c: &mut Criterion) {
@@ -35,14 +47,42 @@ fn bench_verify(c: &mut Criterion) {
     });
 }
 
+fn bench_verify_angle(c: &mut Criterion) {
+    let mgr = make_mgr(150, 20, 60)
+        .with_challenge_type(ChallengeType::AngleDial)
+        .expect("valid challenge type");
+    let ch = mgr.generate_challenge::<SpriteBinary>().unwrap();
+
+    // Widest tolerance, the slowest case back when every angle was tried
+    c.bench_function("verify_angle/max_tolerance", |b| {
+        b.iter(|| mgr.verify_angle(black_box(&ch.challenge_id), 123.0, MAX_ANGLE_TOLERANCE_DEG))
+    });
+}
+
 fn bench_generate_jpeg(c: &mut Criterion) {
     let mut group = c.benchmark_group("generate_e2e_jpeg");
 
     let configs = [(100u32, 70u8), (150u32, 70u8), (200u32, 70u8)];
 
     for (cell, q) in configs {
-        let mgr_uri = make_mgr_with(cell, SpriteFormat::Jpeg { quality: q }, 60);
-        let mgr_bin = make_mgr_with(cell, SpriteFormat::Jpeg { quality: q }, 60);
+        let mgr_uri = make_mgr_with(
+            cell,
+            SpriteFormat::Jpeg {
+                quality: q,
+                progressive: false,
+                chroma_subsampling: JpegSubsampling::Yuv420,
+            },
+            60,
+        );
+        let mgr_bin = make_mgr_with(
+            cell,
+            SpriteFormat::Jpeg {
+                quality: q,
+                progressive: false,
+                chroma_subsampling: JpegSubsampling::Yuv420,
+            },
+            60,
+        );
 
         group.throughput(Throughput::Elements(1));
         group.bench_function(format!("cell{cell}_q{q}/uri"), |b| {
@@ -72,7 +112,7 @@ fn bench_generate_webp(c: &mut Criterion) {
     for (cell, q) in configs {
         let fmt = SpriteFormat::Webp {
             quality: q,
-            lossless: false,
+            method: 4,
         };
         let mgr_uri = make_mgr_with(cell, fmt, 60);
         let mgr_bin = make_mgr_with(cell, fmt, 60);
@@ -97,10 +137,95 @@ fn bench_generate_webp(c: &mut Criterion) {
     group.finish();
 }
 
+fn bench_sprite_buffer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sprite_buffer");
+
+    // 3x3 grid of 150px cells with 4px spacing
+    let side = 3 * 150 + 2 * 4;
+    let bg = Rgba([255, 255, 255, 255]);
+    let pool = SpriteBufferPool::default();
+
+    group.bench_function("alloc", |b| {
+        b.iter(|| black_box(RgbaImage::from_pixel(side, side, bg)));
+    });
+    group.bench_function("pooled", |b| {
+        b.iter(|| {
+            let buf = pool.acquire(side, side, bg);
+            pool.release(black_box(buf));
+        });
+    });
+
+    group.finish();
+}
+
+fn bench_watermark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("watermark_with_noise");
+
+    // Default 3x3 sprite of 150px cells
+    let side = 3 * 150 + 2 * 4;
+    let sprite = DynamicImage::ImageRgba8(RgbaImage::from_pixel(side, side, Rgba([200; 4])));
+
+    let shapes = [
+        ("dots", NoisePattern::Dots),
+        ("lines", NoisePattern::Lines),
+        ("grid", NoisePattern::Grid),
+    ];
+
+    for (name, shape) in shapes {
+        for count in [100u32, 500, 2700, 10000] {
+            let layer = NoiseLayer {
+                count,
+                shape,
+                ..Default::default()
+            };
+
+            group.throughput(Throughput::Elements(count as u64));
+            group.bench_function(format!("{name}/count{count}"), |b| {
+                b.iter_batched(
+                    || sprite.clone(),
+                    |mut img| {
+                        watermark_with_noise(&mut img, layer);
+                        black_box(img)
+                    },
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn bench_rotate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rotate_image");
+
+    // Same angles incorrect tiles are drawn at
+    let angles = [
+        38.0f32, 88.0, 114.0, 138.0, 176.0, 200.0, 229.0, 255.0, 278.0, 314.0, 320.0,
+    ];
+    let bg = Rgba([255, 255, 255, 255]);
+    let tile = DynamicImage::ImageRgba8(RgbaImage::from_fn(150, 150, |x, y| {
+        Rgba([x as u8, y as u8, 128, 255])
+    }));
+
+    group.throughput(Throughput::Elements(1));
+    for angle in angles {
+        group.bench_function(format!("150px/{angle}deg"), |b| {
+            b.iter(|| black_box(rotate_image(&tile, black_box(angle), bg)));
+        });
+    }
+
+    group.finish();
+}
+
 pub fn criterion_benches(c: &mut Criterion) {
     bench_generate_jpeg(c);
     bench_generate_webp(c);
     bench_verify(c);
+    bench_verify_angle(c);
+    bench_sprite_buffer(c);
+    bench_watermark(c);
+    bench_rotate(c);
 }
 
 criterion_group!(benches, criterion_benches);
@@ -0,0 +1,98 @@
+use criterion::{BatchSize, Criterion, Throughput, black_box, criterion_group, criterion_main};
+use geronimo_captcha::{ChallengeInMemoryRegistry, ChallengeRegistry};
+use std::sync::Arc;
+use std::thread;
+
+const THREADS: usize = 8;
+const OPS: usize = 10_000;
+
+/// Fresh registry plus the challenge ids each thread works through.
+fn setup() -> (Arc<ChallengeInMemoryRegistry>, Vec<Vec<String>>) {
+    let registry = Arc::new(ChallengeInMemoryRegistry::new(60, 3));
+    let ids = (0..THREADS)
+        .map(|t| {
+            (0..OPS / THREADS)
+                .map(|i| format!("bench-{t}-{i}"))
+                .collect()
+        })
+        .collect();
+
+    (registry, ids)
+}
+
+fn bench_concurrent(c: &mut Criterion) {
+    let mut group = c.benchmark_group("registry");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements(OPS as u64));
+
+    group.bench_function(format!("{THREADS}threads/{OPS}ops"), |b| {
+        b.iter_batched(
+            setup,
+            |(registry, ids)| {
+                thread::scope(|s| {
+                    for ids in &ids {
+                        let registry = &registry;
+                        s.spawn(move || {
+                            for id in ids {
+                                registry.register(id);
+                                let _ = black_box(registry.check(id));
+                                registry.note_attempt(id, true);
+                                registry.verify(id);
+                            }
+                        });
+                    }
+                });
+                registry
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+/// SipHash vs aHash on the uuid-shaped ids the manager generates.
+#[cfg(feature = "ahash")]
+fn bench_hasher(c: &mut Criterion) {
+    use dashmap::DashMap;
+    use std::hash::BuildHasher;
+
+    fn insert_get<S: BuildHasher + Clone>(map: &DashMap<String, u64, S>, ids: &[String]) {
+        for (i, id) in ids.iter().enumerate() {
+            map.insert(id.clone(), i as u64);
+        }
+        for id in ids {
+            black_box(map.get(id).map(|v| *v));
+        }
+    }
+
+    let ids: Vec<String> = (0..OPS)
+        .map(|_| uuid::Uuid::new_v4().simple().to_string())
+        .collect();
+
+    let mut group = c.benchmark_group("registry_hasher");
+    group.throughput(Throughput::Elements(OPS as u64));
+
+    group.bench_function("siphash", |b| {
+        b.iter_batched(
+            DashMap::<String, u64, std::hash::RandomState>::default,
+            |map| insert_get(&map, &ids),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("ahash", |b| {
+        b.iter_batched(
+            DashMap::<String, u64, ahash::RandomState>::default,
+            |map| insert_get(&map, &ids),
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+#[cfg(not(feature = "ahash"))]
+criterion_group!(benches, bench_concurrent);
+#[cfg(feature = "ahash")]
+criterion_group!(benches, bench_concurrent, bench_hasher);
+criterion_main!(benches);
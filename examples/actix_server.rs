@@ -0,0 +1,114 @@
+//! Minimal actix-web server running the full challenge flow, with the
+//! manager shared through `web::Data`.
+//!
+//! The crate ships no ready-made middleware, `require_captcha` below is one
+//! built with `middleware::from_fn`. It guards `/signup`, which only answers
+//! requests carrying a solved challenge in the `x-captcha-id` and
+//! `x-captcha-answer` headers.
+//!
+//! ```sh
+//! cargo run --example actix_server --features actix,serde_json
+//! curl localhost:3000/captcha
+//! curl -X POST localhost:3000/captcha/verify \
+//!     -H 'content-type: application/json' \
+//!     -d '{"challenge_id": "...", "answer": 5}'
+//! curl -X POST localhost:3000/signup \
+//!     -H 'x-captcha-id: ...' -H 'x-captcha-answer: 5'
+//! ```
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::{ErrorBadRequest, ErrorForbidden, ErrorInternalServerError};
+use actix_web::middleware::{Next, from_fn};
+use actix_web::{App, Error, HttpServer, web};
+use geronimo_captcha::{
+    CaptchaManager, ChallengeInMemoryRegistry, GenerationOptions, NoiseOptions, SpriteUri,
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+struct VerifyRequest {
+    challenge_id: String,
+    answer: u8,
+}
+
+/// Renders a challenge, the sprite comes back as a data URI.
+async fn captcha(mgr: web::Data<CaptchaManager>) -> Result<web::Json<Value>, Error> {
+    // Rendering is CPU bound, keep it off the async workers
+    let challenge = web::block(move || mgr.generate_challenge::<SpriteUri>()).await??;
+
+    Ok(web::Json(challenge.into()))
+}
+
+async fn verify(
+    mgr: web::Data<CaptchaManager>,
+    req: web::Json<VerifyRequest>,
+) -> Result<web::Json<Value>, Error> {
+    let verified = mgr.verify_challenge(&req.challenge_id, req.answer)?;
+    Ok(web::Json(json!({ "verified": verified })))
+}
+
+/// Lets the request through only if its captcha headers hold a solved,
+/// unexpired challenge. Each challenge unlocks a single request.
+async fn require_captcha(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let header = |name| {
+        req.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| ErrorBadRequest(format!("missing {name} header")))
+    };
+    let challenge_id = header("x-captcha-id")?;
+    let answer = header("x-captcha-answer")?
+        .parse::<u8>()
+        .map_err(|_| ErrorBadRequest("x-captcha-answer must be a tile number"))?;
+
+    let mgr = req
+        .app_data::<web::Data<CaptchaManager>>()
+        .ok_or_else(|| ErrorInternalServerError("captcha manager not configured"))?;
+    if !mgr.verify_challenge(challenge_id, answer)? {
+        return Err(ErrorForbidden("captcha not solved"));
+    }
+
+    next.call(req).await
+}
+
+async fn signup() -> web::Json<Value> {
+    web::Json(json!({ "signed_up": true }))
+}
+
+#[actix_web::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let ttl_secs = 60;
+    let secret = std::env::var("CAPTCHA_SECRET").unwrap_or_else(|_| "your-secret-key".into());
+    let registry = Arc::new(ChallengeInMemoryRegistry::new(ttl_secs, 3));
+    let mgr = web::Data::new(CaptchaManager::new(
+        secret,
+        ttl_secs,
+        NoiseOptions::default(),
+        Some(registry),
+        GenerationOptions::default(),
+    )?);
+
+    println!("listening on http://0.0.0.0:3000");
+    HttpServer::new(move || {
+        App::new()
+            .app_data(mgr.clone())
+            .route("/captcha", web::get().to(captcha))
+            .route("/captcha/verify", web::post().to(verify))
+            .service(
+                web::resource("/signup")
+                    .wrap(from_fn(require_captcha))
+                    .route(web::post().to(signup)),
+            )
+    })
+    .bind(("0.0.0.0", 3000))?
+    .run()
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,69 @@
+//! Minimal axum server running the full challenge flow.
+//!
+//! ```sh
+//! cargo run --example axum_server --features axum,serde_json
+//! curl localhost:3000/captcha
+//! curl -X POST localhost:3000/captcha/verify \
+//!     -H 'content-type: application/json' \
+//!     -d '{"challenge_id": "...", "answer": 5}'
+//! ```
+
+use axum::extract::State;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use geronimo_captcha::{
+    CaptchaError, CaptchaManager, ChallengeInMemoryRegistry, GenerationOptions, NoiseOptions,
+    SpriteUri,
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+struct VerifyRequest {
+    challenge_id: String,
+    answer: u8,
+}
+
+/// Renders a challenge, the sprite comes back as a data URI.
+async fn captcha(State(mgr): State<Arc<CaptchaManager>>) -> Result<Json<Value>, CaptchaError> {
+    // Rendering is CPU bound, keep it off the async workers
+    let challenge = tokio::task::spawn_blocking(move || mgr.generate_challenge::<SpriteUri>())
+        .await
+        .map_err(|e| CaptchaError::Internal(e.to_string()))??;
+
+    Ok(Json(challenge.into()))
+}
+
+async fn verify(
+    State(mgr): State<Arc<CaptchaManager>>,
+    Json(req): Json<VerifyRequest>,
+) -> Result<Json<Value>, CaptchaError> {
+    let verified = mgr.verify_challenge(&req.challenge_id, req.answer)?;
+    Ok(Json(json!({ "verified": verified })))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let ttl_secs = 60;
+    let secret = std::env::var("CAPTCHA_SECRET").unwrap_or_else(|_| "your-secret-key".into());
+    let registry = Arc::new(ChallengeInMemoryRegistry::new(ttl_secs, 3));
+    let mgr = CaptchaManager::new(
+        secret,
+        ttl_secs,
+        NoiseOptions::default(),
+        Some(registry),
+        GenerationOptions::default(),
+    )?;
+
+    let app = Router::new()
+        .route("/captcha", get(captcha))
+        .route("/captcha/verify", post(verify))
+        .with_state(Arc::new(mgr));
+
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
+    println!("listening on http://{}", listener.local_addr()?);
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
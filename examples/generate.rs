@@ -0,0 +1,29 @@
+//! Generates one challenge and saves its sprite to the temp directory.
+//!
+//! Log output is filtered with `CAPTCHA_LOG_LEVEL` (default `info`):
+//!
+//! ```sh
+//! CAPTCHA_LOG_LEVEL=debug cargo run --example generate
+//! ```
+
+use geronimo_captcha::{CaptchaManager, SpriteBinary};
+use tracing_subscriber::EnvFilter;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_env("CAPTCHA_LOG_LEVEL").unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    let mgr = CaptchaManager::default();
+    let challenge = mgr.generate_challenge::<SpriteBinary>()?;
+
+    let path = std::env::temp_dir().join("geronimo-captcha-sprite.jpg");
+    challenge.save_sprite(&path)?;
+
+    println!("sprite: {}", path.display());
+    println!("challenge_id: {}", challenge.challenge_id);
+
+    Ok(())
+}
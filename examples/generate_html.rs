@@ -0,0 +1,50 @@
+//! Writes `examples/captcha.html`, a bare form embedding one challenge, for
+//! checking how a sprite looks in the browser.
+//!
+//! ```sh
+//! cargo run --example generate_html
+//! ```
+
+use geronimo_captcha::{CaptchaManager, SpriteUri};
+use std::fmt::Write;
+use std::path::Path;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mgr = CaptchaManager::default();
+    let challenge = mgr.generate_challenge::<SpriteUri>()?;
+
+    let mut options = String::new();
+    for n in 1..=challenge.cols * challenge.rows {
+        writeln!(
+            options,
+            r#"        <label><input type="radio" name="answer" value="{n}" required> {n}</label>"#
+        )?;
+    }
+
+    // Base64 and the challenge id never contain characters that need escaping
+    let html = format!(
+        r#"<!doctype html>
+<html>
+  <head><meta charset="utf-8"><title>geronimo-captcha</title></head>
+  <body>
+    <form method="post">
+      <p>Pick the tile that is the right way up</p>
+      <img src="{sprite}" alt="captcha">
+      <input type="hidden" name="challenge_id" value="{id}">
+      <fieldset>
+{options}      </fieldset>
+      <button type="submit">Verify</button>
+    </form>
+  </body>
+</html>
+"#,
+        sprite = challenge.sprite.0,
+        id = challenge.challenge_id,
+    );
+
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/captcha.html");
+    std::fs::write(&path, html)?;
+    println!("wrote {}", path.display());
+
+    Ok(())
+}
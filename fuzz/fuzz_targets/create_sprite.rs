@@ -0,0 +1,14 @@
+#![no_main]
+
+use geronimo_captcha::{GenerationOptions, MIN_CELL_SIZE, NoiseOptions, create_sprite};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let opts = GenerationOptions {
+        cell_size: MIN_CELL_SIZE,
+        ..Default::default()
+    };
+
+    // Malformed images must surface as a CaptchaError, never a panic.
+    let _ = create_sprite(data, &opts, &NoiseOptions::default(), None, None);
+});
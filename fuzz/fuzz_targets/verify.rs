@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let challenge_id = String::from_utf8_lossy(data);
+
+    // Any input must come back as a plain bool, never a panic.
+    let _: bool = geronimo_captcha::verify(b"secret", &challenge_id, &[5], 60);
+});
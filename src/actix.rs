@@ -0,0 +1,77 @@
+use crate::{CaptchaError, RegistryCheckResult};
+
+use actix_web::error::{
+    ErrorBadRequest, ErrorForbidden, ErrorInternalServerError, ErrorTooManyRequests, InternalError,
+};
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::{Error, HttpResponse};
+use tracing::error;
+
+impl From<CaptchaError> for Error {
+    fn from(err: CaptchaError) -> Self {
+        match err {
+            CaptchaError::InvalidInput(_) => ErrorBadRequest(err),
+            CaptchaError::Registry(RegistryCheckResult::MaxAttemptsLimitExceeded { .. }) => {
+                ErrorTooManyRequests(err)
+            }
+            CaptchaError::Registry(_) => ErrorForbidden(err),
+            CaptchaError::RateLimit { retry_after_secs } => {
+                let response = HttpResponse::TooManyRequests()
+                    .insert_header((RETRY_AFTER, retry_after_secs))
+                    .finish();
+
+                InternalError::from_response(err, response).into()
+            }
+            CaptchaError::Decode(_)
+            | CaptchaError::Encode(_)
+            | CaptchaError::Io(_)
+            | CaptchaError::Internal(_) => {
+                error!(error = ?err, "captcha request failed");
+                ErrorInternalServerError("internal server error")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+
+    fn status(err: CaptchaError) -> StatusCode {
+        Error::from(err).as_response_error().status_code()
+    }
+
+    #[test]
+    fn test_status_codes() {
+        assert_eq!(
+            status(CaptchaError::InvalidInput("bad".into())),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(CaptchaError::Registry(RegistryCheckResult::NotRegistered)),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(CaptchaError::Registry(
+                RegistryCheckResult::MaxAttemptsLimitExceeded { remaining: 0 }
+            )),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            status(CaptchaError::Internal("boom".into())),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_sets_retry_after() {
+        let err = Error::from(CaptchaError::RateLimit {
+            retry_after_secs: 30,
+        });
+        let response = err.error_response();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "30");
+    }
+}
@@ -0,0 +1,77 @@
+use crate::{CaptchaError, RegistryCheckResult};
+
+use axum::Json;
+use axum::http::StatusCode;
+use axum::http::header::RETRY_AFTER;
+use axum::response::{IntoResponse, Response};
+use serde_json::json;
+use tracing::error;
+
+impl IntoResponse for CaptchaError {
+    fn into_response(self) -> Response {
+        let status = match &self {
+            CaptchaError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            CaptchaError::Registry(RegistryCheckResult::MaxAttemptsLimitExceeded { .. }) => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            CaptchaError::Registry(_) => StatusCode::FORBIDDEN,
+            CaptchaError::RateLimit { retry_after_secs } => {
+                let body = Json(json!({ "error": self.to_string() }));
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(RETRY_AFTER, retry_after_secs.to_string())],
+                    body,
+                )
+                    .into_response();
+            }
+            CaptchaError::Decode(_)
+            | CaptchaError::Encode(_)
+            | CaptchaError::Io(_)
+            | CaptchaError::Internal(_) => {
+                error!(error = ?self, "captcha request failed");
+                let body = Json(json!({ "error": "internal server error" }));
+                return (StatusCode::INTERNAL_SERVER_ERROR, body).into_response();
+            }
+        };
+
+        (status, Json(json!({ "error": self.to_string() }))).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_codes() {
+        let status = |err: CaptchaError| err.into_response().status();
+
+        assert_eq!(
+            status(CaptchaError::InvalidInput("bad".into())),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(CaptchaError::Registry(RegistryCheckResult::AlreadyVerified)),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(CaptchaError::Internal("boom".into())),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_sets_retry_after() {
+        let response = CaptchaError::RateLimit {
+            retry_after_secs: 30,
+        }
+        .into_response();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "30");
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "application/json"
+        );
+    }
+}
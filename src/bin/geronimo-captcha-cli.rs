@@ -0,0 +1,76 @@
+use clap::{Parser, ValueEnum};
+use geronimo_captcha::{
+    CaptchaManager, GenerationOptions, JpegSubsampling, NoiseOptions, SpriteBinary, SpriteFormat,
+};
+use std::path::PathBuf;
+
+/// Fixed secret, challenges produced by the CLI are for previews only.
+const PREVIEW_SECRET: &str = "geronimo-captcha-cli-preview";
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Jpeg,
+    Webp,
+    WebpLossless,
+}
+
+/// Generate a captcha challenge sprite for previewing.
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// Tile size in pixels
+    #[arg(long, default_value_t = 150)]
+    cell_size: u32,
+
+    /// Sprite encoding
+    #[arg(long, value_enum, default_value_t = Format::Jpeg)]
+    format: Format,
+
+    /// JPEG/WebP quality (0–100) or lossless WebP compression level (0–9)
+    #[arg(long, default_value_t = 70)]
+    quality: u8,
+
+    /// Where to write the sprite image
+    #[arg(long)]
+    output: PathBuf,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let sprite_format = match args.format {
+        Format::Jpeg => SpriteFormat::Jpeg {
+            quality: args.quality,
+            progressive: false,
+            chroma_subsampling: JpegSubsampling::Yuv420,
+        },
+        Format::Webp => SpriteFormat::Webp {
+            quality: args.quality,
+            method: 4,
+        },
+        Format::WebpLossless => SpriteFormat::WebpLossless {
+            compression_level: args.quality,
+        },
+    };
+
+    let gen_opts = GenerationOptions {
+        cell_size: args.cell_size,
+        sprite_format,
+        ..Default::default()
+    };
+
+    let mgr = CaptchaManager::new(
+        PREVIEW_SECRET.into(),
+        60,
+        NoiseOptions::default(),
+        None,
+        gen_opts,
+    )?;
+
+    let challenge = mgr.generate_challenge::<SpriteBinary>()?;
+    challenge.save_sprite(&args.output)?;
+
+    println!("{}", challenge.challenge_id);
+
+    Ok(())
+}
@@ -0,0 +1,243 @@
+use crate::image::rotate_image;
+#[cfg(feature = "test-utils")]
+use crate::sprite::SpriteBinary;
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use lru::LruCache;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Rotations of one base image by angle bits.
+type Rotations = HashMap<u32, Arc<RgbaImage>>;
+
+/// Lazily filled cache of rotated tiles, keyed by `(image_hash, angle)`.
+///
+/// Holds the rotations of at most `capacity` base images. Adding another
+/// one drops the least recently used image.
+pub struct RotatedImageCache {
+    entries: Mutex<LruCache<u64, Rotations>>,
+}
+
+impl Default for RotatedImageCache {
+    fn default() -> Self {
+        Self::with_capacity(Self::DEFAULT_CAPACITY)
+    }
+}
+
+impl RotatedImageCache {
+    /// Base images kept by [`RotatedImageCache::new`], room for the
+    /// bundled samples and a few runtime-added images.
+    pub const DEFAULT_CAPACITY: usize = 16;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps the rotations of at most `images` base images, at least one.
+    pub fn with_capacity(images: usize) -> Self {
+        let capacity = NonZeroUsize::new(images).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            entries: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Hash identifying a base image as rendered for a given cell size and
+    /// background, since both change the rotated output. Hashes the whole
+    /// buffer, so compute it once per base image.
+    pub fn image_hash(base_buf: &[u8], cell_size: u32, bg: Rgba<u8>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        base_buf.hash(&mut hasher);
+        cell_size.hash(&mut hasher);
+        bg.0.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Returns every angle of `image_hash` if all of them are cached.
+    pub fn get_all(&self, image_hash: u64, angles: &[f32]) -> Option<Vec<(f32, Arc<RgbaImage>)>> {
+        let mut entries = self.lock();
+        let rotations = entries.get(&image_hash)?;
+        angles
+            .iter()
+            .map(|&a| rotations.get(&a.to_bits()).map(|img| (a, Arc::clone(img))))
+            .collect()
+    }
+
+    /// Returns the rotations of `base` for `angles`, computing and storing
+    /// the ones that are missing.
+    pub fn get_or_rotate(
+        &self,
+        image_hash: u64,
+        base: &DynamicImage,
+        angles: &[f32],
+        bg: Rgba<u8>,
+    ) -> Vec<(f32, Arc<RgbaImage>)> {
+        let missing: Vec<f32> = {
+            let entries = self.lock();
+            let cached = entries.peek(&image_hash);
+            angles
+                .iter()
+                .copied()
+                .filter(|a| cached.is_none_or(|r| !r.contains_key(&a.to_bits())))
+                .collect()
+        };
+
+        // Rotate without holding the lock
+        let fresh = rotate_all(base, &missing, bg);
+
+        let mut entries = self.lock();
+        let rotations = entries.get_or_insert_mut(image_hash, Rotations::new);
+        angles
+            .iter()
+            .map(|&a| {
+                let img = rotations.entry(a.to_bits()).or_insert_with(|| {
+                    fresh
+                        .iter()
+                        .find(|(m, _)| m.to_bits() == a.to_bits())
+                        .map(|(_, img)| Arc::clone(img))
+                        // Evicted by a concurrent `clear` since the scan above.
+                        .unwrap_or_else(|| Arc::new(rotate_image(base, a, bg).to_rgba8()))
+                });
+
+                (a, Arc::clone(img))
+            })
+            .collect()
+    }
+
+    /// Drops every cached rotation of `image_hash`.
+    pub fn evict(&self, image_hash: u64) {
+        self.lock().pop(&image_hash);
+    }
+
+    /// Number of cached rotations across all base images.
+    pub fn len(&self) -> usize {
+        self.lock().iter().map(|(_, r)| r.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LruCache<u64, Rotations>> {
+        // Tiles are inserted whole, a panic can't leave one half-written.
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Most recently generated sprites by challenge id, `test-utils` only. Lets
+/// tests check that replaying a seeded challenge renders the same sprite.
+#[cfg(feature = "test-utils")]
+pub struct SpriteCache {
+    entries: Mutex<LruCache<String, SpriteBinary>>,
+}
+
+#[cfg(feature = "test-utils")]
+impl SpriteCache {
+    /// Keeps at most `capacity` sprites, at least one.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            entries: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Stores `sprite` for `challenge_id`, returning the one it replaced.
+    pub fn insert(&self, challenge_id: String, sprite: SpriteBinary) -> Option<SpriteBinary> {
+        self.lock().put(challenge_id, sprite)
+    }
+
+    pub fn get(&self, challenge_id: &str) -> Option<SpriteBinary> {
+        self.lock().get(challenge_id).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LruCache<String, SpriteBinary>> {
+        // A panic while holding the lock can't leave the LRU half-updated.
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+pub(crate) fn rotate_all(
+    base: &DynamicImage,
+    angles: &[f32],
+    bg: Rgba<u8>,
+) -> Vec<(f32, Arc<RgbaImage>)> {
+    #[cfg(feature = "parallel")]
+    {
+        angles
+            .par_iter()
+            .map(|&a| (a, Arc::new(rotate_image(base, a, bg).to_rgba8())))
+            .collect()
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        angles
+            .iter()
+            .map(|&a| (a, Arc::new(rotate_image(base, a, bg).to_rgba8())))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotations_are_cached_and_reused() {
+        let cache = RotatedImageCache::new();
+        let base = DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 8, Rgba([10, 20, 30, 255])));
+        let bg = Rgba([255, 255, 255, 255]);
+        let hash = RotatedImageCache::image_hash(b"base", 8, bg);
+
+        assert!(cache.get_all(hash, &[0.0, 90.0]).is_none());
+
+        let first = cache.get_or_rotate(hash, &base, &[0.0, 90.0], bg);
+        assert_eq!(cache.len(), 2);
+
+        let second = cache.get_all(hash, &[90.0, 0.0]).expect("cached angles");
+        assert!(Arc::ptr_eq(&first[1].1, &second[0].1));
+        assert!(Arc::ptr_eq(&first[0].1, &second[1].1));
+
+        cache.get_or_rotate(hash, &base, &[90.0, 180.0], bg);
+        assert_eq!(cache.len(), 3);
+
+        let other = RotatedImageCache::image_hash(b"base", 16, bg);
+        assert_ne!(hash, other);
+        assert!(cache.get_all(other, &[0.0]).is_none());
+    }
+
+    #[test]
+    fn test_least_recently_used_image_is_dropped() {
+        let cache = RotatedImageCache::with_capacity(2);
+        let base = DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 8, Rgba([10, 20, 30, 255])));
+        let bg = Rgba([255, 255, 255, 255]);
+        let [a, b, c] = [b"a", b"b", b"c"].map(|buf| RotatedImageCache::image_hash(buf, 8, bg));
+
+        cache.get_or_rotate(a, &base, &[0.0, 90.0], bg);
+        cache.get_or_rotate(b, &base, &[0.0], bg);
+        assert!(cache.get_all(a, &[0.0]).is_some());
+
+        cache.get_or_rotate(c, &base, &[0.0], bg);
+        assert_eq!(cache.len(), 3);
+        assert!(cache.get_all(a, &[0.0, 90.0]).is_some());
+        assert!(cache.get_all(b, &[0.0]).is_none());
+
+        cache.evict(a);
+        assert_eq!(cache.len(), 1);
+    }
+}
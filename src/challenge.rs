@@ -1,89 +1,453 @@
+#[cfg(feature = "render")]
+use crate::cache::RotatedImageCache;
+#[cfg(feature = "render")]
+use crate::config::CaptchaConfig;
 use crate::error::{CaptchaError, Result};
-use crate::image::{NoiseOptions, encode_image, watermark_with_noise};
-use crate::sprite::{SpriteFormat, SpriteTarget, create_sprite};
+#[cfg(feature = "render")]
+use crate::image::{ColorJitter, NoiseOptions, encode_image, watermark_with_noise};
+#[cfg(feature = "render")]
+use crate::pool::SpriteBufferPool;
+#[cfg(feature = "render")]
+use crate::sprite::{
+    FONT, INCORRECT_ANGLES, SpriteAnswer, SpriteBinary, SpriteFormat, SpriteTarget, SpriteUri,
+    add_instruction_banner, banner_height, create_challenge_sprite,
+};
+#[cfg(all(
+    any(feature = "render", not(feature = "no-std")),
+    any(test, feature = "test-utils", not(feature = "render"))
+))]
 use crate::utils::get_timestamp;
+#[cfg(feature = "render")]
+use crate::utils::{Clock, SystemClock};
 
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 use base64::{Engine as _, prelude::BASE64_STANDARD};
+use core::str::FromStr;
 use hmac::{Hmac, Mac};
+#[cfg(feature = "render")]
 use image::{DynamicImage, Limits};
 use sha2::Sha256;
+#[cfg(feature = "render")]
+use std::fs;
+#[cfg(feature = "render")]
+use std::path::Path;
 use subtle::ConstantTimeEq;
 use uuid::Uuid;
 
 type HmacSha256 = Hmac<Sha256>;
 
+#[cfg(feature = "render")]
+#[must_use]
 pub struct CaptchaChallenge<T> {
     pub sprite: T,
+    /// Sprite before encoding, `test-utils` only.
     #[cfg(any(test, feature = "test-utils"))]
     pub sprite_dbg: DynamicImage,
     pub challenge_id: String,
     pub timestamp: u64,
+    /// Unix time after which the challenge no longer verifies.
+    pub expires_at: u64,
+    pub cols: u32,
+    pub rows: u32,
+    /// 1-based index of the upright tile, `test-utils` only. Enable the
+    /// feature under `[dev-dependencies]` to keep it out of release builds.
     #[cfg(any(test, feature = "test-utils"))]
     pub correct_number: u8,
+    /// Every correct tile in ascending order, `test-utils` only. Same as
+    /// `[correct_number]` except for [`ChallengeType::SelectAll`].
+    #[cfg(any(test, feature = "test-utils"))]
+    pub correct_numbers: Vec<u8>,
+    /// Clockwise angle the [`ChallengeType::AngleDial`] tile is drawn at,
+    /// `test-utils` only. 0 for other types.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub dial_angle: u16,
 }
 
-#[derive(Clone)]
+#[cfg(feature = "render")]
+impl CaptchaChallenge<SpriteBinary> {
+    pub fn save_sprite(&self, path: &Path) -> std::io::Result<()> {
+        fs::write(path, &self.sprite.bytes)
+    }
+}
+
+#[cfg(all(feature = "render", feature = "serde_json"))]
+impl CaptchaChallenge<SpriteUri> {
+    /// Everything a front-end needs to render the challenge, without the answer.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "challenge_id": self.challenge_id,
+            "sprite": self.sprite.0,
+            "expires_at": self.expires_at,
+            "cols": self.cols,
+            "rows": self.rows,
+        })
+    }
+}
+
+#[cfg(all(feature = "render", feature = "serde_json"))]
+impl From<CaptchaChallenge<SpriteUri>> for serde_json::Value {
+    fn from(challenge: CaptchaChallenge<SpriteUri>) -> Self {
+        challenge.to_json()
+    }
+}
+
+#[cfg(feature = "render")]
+impl CaptchaChallenge<SpriteUri> {
+    /// Decode the base64 data URI and write raw image bytes to `path`.
+    pub fn save_sprite_decoded(&self, path: &Path) -> Result<()> {
+        let (_, payload) = self
+            .sprite
+            .0
+            .split_once(";base64,")
+            .ok_or_else(|| CaptchaError::Internal("malformed sprite data uri".into()))?;
+        let bytes = BASE64_STANDARD
+            .decode(payload)
+            .map_err(|e| CaptchaError::Internal(format!("decode sprite data uri: {e}")))?;
+
+        fs::write(path, bytes).map_err(CaptchaError::Io)
+    }
+}
+
+#[cfg(feature = "render")]
+#[derive(Clone, Debug, PartialEq)]
 pub struct GenerationOptions {
     pub cell_size: u32,
     pub sprite_format: SpriteFormat,
     pub limits: Option<Limits>,
+    /// Upper bound on `cell_size`, the sprite buffer grows with its square.
+    pub max_cell_size: u32,
+    /// Visible instructions drawn in a banner above the sprite.
+    pub instruction_text: Option<String>,
+    /// Fill the gaps between tiles with dark lines.
+    pub draw_grid_lines: bool,
+    /// Grid columns, `cols * rows` tiles in total.
+    pub cols: u32,
+    pub rows: u32,
+    /// Sharpen each grid tile after it is scaled down into its cell.
+    pub sharpen_after_resize: bool,
+    /// Random brightness, contrast and saturation shift per tile.
+    pub color_jitter: Option<ColorJitter>,
+    /// Chance of each incorrect tile being drawn in grayscale, the correct
+    /// tile always keeps its colours. At most
+    /// [`MAX_GRAYSCALE_TILE_PROBABILITY`]: the more wrong tiles turn grey,
+    /// the more "pick the colour tile" gives the answer away.
+    pub grayscale_tile_probability: f64,
+    /// Pull tile corners inwards at random for a slight 3D tilt.
+    pub perspective_warp: bool,
+}
+
+/// Highest accepted `grayscale_tile_probability`. At 1.0 the answer is the
+/// only colour tile.
+#[cfg(feature = "render")]
+pub const MAX_GRAYSCALE_TILE_PROBABILITY: f64 = 0.5;
+
+/// Smallest `cell_size` that still leaves room for a readable tile and label.
+#[cfg(feature = "render")]
+pub const MIN_CELL_SIZE: u32 = 32;
+
+#[cfg(feature = "render")]
+impl GenerationOptions {
+    pub fn tile_count(&self) -> u32 {
+        self.cols.saturating_mul(self.rows)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.cell_size < MIN_CELL_SIZE {
+            return Err(CaptchaError::InvalidInput(format!(
+                "cell_size must be at least {MIN_CELL_SIZE}"
+            )));
+        }
+        if self.cell_size > self.max_cell_size {
+            return Err(CaptchaError::InvalidInput(format!(
+                "cell_size must be at most {}",
+                self.max_cell_size
+            )));
+        }
+        if self.tile_count() < 2 {
+            return Err(CaptchaError::InvalidInput(
+                "grid must have at least 2 tiles".into(),
+            ));
+        }
+        let needed = self.tile_count() - 1;
+        if needed as usize > INCORRECT_ANGLES.len() {
+            return Err(CaptchaError::InvalidInput(format!(
+                "a {}x{} grid needs {needed} incorrect angles, only {} available",
+                self.cols,
+                self.rows,
+                INCORRECT_ANGLES.len()
+            )));
+        }
+        if let Some(jitter) = &self.color_jitter {
+            jitter.validate()?;
+        }
+        if !(0.0..=MAX_GRAYSCALE_TILE_PROBABILITY).contains(&self.grayscale_tile_probability) {
+            return Err(CaptchaError::InvalidInput(format!(
+                "grayscale_tile_probability must be between 0 and {MAX_GRAYSCALE_TILE_PROBABILITY}"
+            )));
+        }
+
+        Ok(())
+    }
 }
 
+#[cfg(feature = "render")]
 impl Default for GenerationOptions {
     fn default() -> Self {
         Self {
             cell_size: 150,
             sprite_format: SpriteFormat::default(),
             limits: None,
+            max_cell_size: 1024,
+            instruction_text: None,
+            draw_grid_lines: false,
+            cols: 3,
+            rows: 3,
+            sharpen_after_resize: false,
+            color_jitter: None,
+            grayscale_tile_probability: 0.0,
+            perspective_warp: false,
         }
     }
 }
 
-pub fn generate<T: SpriteTarget>(
-    base_buf: &[u8],
-    secret: &[u8],
-    opts: &GenerationOptions,
+/// Puzzle mechanic a challenge sprite is built around.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ChallengeType {
+    /// Pick the single upright tile among rotated copies.
+    #[default]
+    Rotation,
+    /// Select every upright tile, `correct_count` of them.
+    SelectAll { correct_count: u8 },
+    /// Turn a single rotated tile back upright with a dial, checked with
+    /// `verify_angle` against a tolerance.
+    AngleDial,
+}
+
+#[cfg(feature = "render")]
+impl ChallengeType {
+    /// Checks the type fits the grid described by `opts`.
+    pub fn validate(&self, opts: &GenerationOptions) -> Result<()> {
+        let max = opts.tile_count().saturating_sub(1);
+        if let ChallengeType::SelectAll { correct_count } = *self
+            && !(1..=max).contains(&u32::from(correct_count))
+        {
+            return Err(CaptchaError::InvalidInput(format!(
+                "correct_count must be between 1 and {max}"
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Builds challenges of one [`ChallengeType`] from a base image.
+#[cfg(feature = "render")]
+#[derive(Clone)]
+pub struct ChallengeBuilder<'a> {
+    challenge_type: ChallengeType,
+    opts: GenerationOptions,
     noise: NoiseOptions,
-) -> Result<CaptchaChallenge<T>> {
-    let (mut sprite, correct_number) = create_sprite(base_buf, opts)?;
-    watermark_with_noise(&mut sprite, noise);
+    cache: Option<&'a RotatedImageCache>,
+    image_hash: Option<u64>,
+    buffers: Option<&'a SpriteBufferPool>,
+    clock: &'a dyn Clock,
+    ttl: u64,
+}
 
-    let rgb = sprite.to_rgb8();
-    let dyn_rgb = DynamicImage::ImageRgb8(rgb);
+#[cfg(feature = "render")]
+impl<'a> ChallengeBuilder<'a> {
+    pub fn new(challenge_type: ChallengeType) -> Self {
+        Self {
+            challenge_type,
+            opts: GenerationOptions::default(),
+            noise: NoiseOptions::default(),
+            cache: None,
+            image_hash: None,
+            buffers: None,
+            clock: &SystemClock,
+            ttl: CaptchaConfig::default().ttl_secs,
+        }
+    }
 
-    let (sprite_buf, mime) =
-        encode_image(&dyn_rgb, &opts.sprite_format).map_err(CaptchaError::Encode)?;
+    pub fn options(mut self, opts: GenerationOptions) -> Self {
+        self.opts = opts;
+        self
+    }
 
-    let sprite = T::from_bytes(sprite_buf, mime);
+    pub fn noise(mut self, noise: NoiseOptions) -> Self {
+        self.noise = noise;
+        self
+    }
 
-    let (challenge_id, timestamp) = build_challenge_id(correct_number, secret)?;
+    /// Reuse rotated tiles across builds of the same base image.
+    pub fn rotation_cache(mut self, cache: &'a RotatedImageCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
 
-    #[cfg(any(test, feature = "test-utils"))]
-    let challenge = CaptchaChallenge {
-        sprite,
-        sprite_dbg: dyn_rgb,
-        challenge_id,
-        timestamp,
-        correct_number,
-    };
-    #[cfg(not(any(test, feature = "test-utils")))]
-    let challenge = CaptchaChallenge {
-        sprite,
-        challenge_id,
-        timestamp,
-    };
+    /// [`RotatedImageCache::image_hash`] of the base image passed to
+    /// `build`, so the rotation cache doesn't hash the image on every build.
+    pub fn image_hash(mut self, hash: u64) -> Self {
+        self.image_hash = Some(hash);
+        self
+    }
+
+    /// Reuse sprite buffers across builds.
+    pub fn buffer_pool(mut self, buffers: &'a SpriteBufferPool) -> Self {
+        self.buffers = Some(buffers);
+        self
+    }
+
+    /// Lifetime used for [`CaptchaChallenge::expires_at`], should match the
+    /// TTL the challenge is verified with.
+    pub fn ttl(mut self, ttl_secs: u64) -> Self {
+        self.ttl = ttl_secs;
+        self
+    }
+
+    /// Clock used to timestamp the challenge id.
+    pub fn clock(mut self, clock: &'a dyn Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn challenge_type(&self) -> ChallengeType {
+        self.challenge_type
+    }
+
+    pub fn build<T: SpriteTarget>(
+        &self,
+        base_buf: &[u8],
+        secret: &[u8],
+    ) -> Result<CaptchaChallenge<T>> {
+        let (mut sprite, answer) = create_challenge_sprite(
+            self.challenge_type,
+            base_buf,
+            &self.opts,
+            &self.noise,
+            self.cache,
+            self.image_hash,
+            self.buffers,
+        )?;
+        watermark_with_noise(&mut sprite, self.noise.post_noise);
+
+        let rgb = match &self.opts.instruction_text {
+            Some(text) => {
+                add_instruction_banner(&sprite, text, &FONT, banner_height(&self.opts)).to_rgb8()
+            }
+            None => sprite.to_rgb8(),
+        };
+        if let (Some(pool), DynamicImage::ImageRgba8(buf)) = (self.buffers, sprite) {
+            pool.release(buf);
+        }
+        let dyn_rgb = DynamicImage::ImageRgb8(rgb);
+
+        let (sprite_buf, mime) =
+            encode_image(&dyn_rgb, &self.opts.sprite_format).map_err(CaptchaError::Encode)?;
+
+        let sprite = T::from_bytes(sprite_buf, mime);
+
+        let now = self.clock.now_secs();
+        let (challenge_id, timestamp) = match &answer {
+            SpriteAnswer::Tiles(correct) => {
+                let payload = tiles_payload(correct).ok_or_else(|| {
+                    CaptchaError::Internal("correct tile number out of range".into())
+                })?;
+                sign_challenge_id(&payload, secret, now)?
+            }
+            SpriteAnswer::Angle(angle) => sign_dial_id(*angle, secret, now)?,
+        };
+        let expires_at = timestamp.saturating_add(self.ttl);
+        // The dial is a single tile, not a cell of the configured grid
+        let (cols, rows) = match self.challenge_type {
+            ChallengeType::AngleDial => (1, 1),
+            _ => (self.opts.cols, self.opts.rows),
+        };
+
+        #[cfg(any(test, feature = "test-utils"))]
+        let challenge = {
+            let (correct_numbers, dial_angle) = match answer {
+                SpriteAnswer::Tiles(correct) => (correct, 0),
+                SpriteAnswer::Angle(angle) => (Vec::new(), angle),
+            };
+            CaptchaChallenge {
+                sprite,
+                sprite_dbg: dyn_rgb,
+                challenge_id,
+                timestamp,
+                expires_at,
+                cols,
+                rows,
+                correct_number: correct_numbers.first().copied().unwrap_or(0),
+                correct_numbers,
+                dial_angle,
+            }
+        };
+        #[cfg(not(any(test, feature = "test-utils")))]
+        let challenge = CaptchaChallenge {
+            sprite,
+            challenge_id,
+            timestamp,
+            expires_at,
+            cols,
+            rows,
+        };
 
-    Ok(challenge)
+        Ok(challenge)
+    }
+}
+
+/// Signs a tile challenge id for `correct`, stamped with the system clock.
+#[cfg(all(
+    any(feature = "render", not(feature = "no-std")),
+    any(feature = "test-utils", not(feature = "render"))
+))]
+#[doc(hidden)]
+pub fn build_challenge_id(correct: &[u8], secret: &[u8]) -> Result<(String, u64)> {
+    build_challenge_id_at(correct, secret, get_timestamp())
+}
+
+/// Same as [`build_challenge_id`], stamped with `now` instead of the system clock.
+#[cfg(any(feature = "test-utils", not(feature = "render")))]
+pub fn build_challenge_id_at(correct: &[u8], secret: &[u8], now: u64) -> Result<(String, u64)> {
+    let payload = tiles_payload(correct)
+        .ok_or_else(|| CaptchaError::InvalidInput("correct tile number out of range".into()))?;
+
+    sign_challenge_id(&payload, secret, now)
 }
 
-fn build_challenge_id(correct_number: u8, secret: &[u8]) -> Result<(String, u64)> {
-    let timestamp = get_timestamp();
-    let nonce = Uuid::new_v4().to_string();
+/// Signs `payload`, the encoded answer, into a fresh challenge id.
+fn sign_challenge_id(payload: &[u8], secret: &[u8], timestamp: u64) -> Result<(String, u64)> {
+    sign_with_nonce(Uuid::new_v4().to_string(), payload, secret, timestamp)
+}
+
+/// Signs a dial `angle` into a fresh challenge id. The angle rides in the
+/// nonce as `uuid.xxxx`, masked by [`dial_mask`], so verifying reads it
+/// back instead of trying every angle.
+#[cfg(feature = "render")]
+fn sign_dial_id(angle: u16, secret: &[u8], timestamp: u64) -> Result<(String, u64)> {
+    let uuid = Uuid::new_v4().to_string();
+    let mask = dial_mask(secret, &uuid)
+        .ok_or_else(|| CaptchaError::Internal("create HMAC: invalid key length".into()))?;
+    let nonce = format!("{uuid}.{:04x}", angle ^ mask);
+
+    sign_with_nonce(nonce, &dial_payload(angle), secret, timestamp)
+}
 
+fn sign_with_nonce(
+    nonce: String,
+    payload: &[u8],
+    secret: &[u8],
+    timestamp: u64,
+) -> Result<(String, u64)> {
     let mut mac = HmacSha256::new_from_slice(secret)
         .map_err(|e| CaptchaError::Internal(format!("create HMAC: {e}")))?;
     mac.update(nonce.as_bytes());
-    mac.update(&[correct_number]);
+    mac.update(payload);
     mac.update(&timestamp.to_be_bytes());
 
     let code = BASE64_STANDARD.encode(mac.finalize().into_bytes());
@@ -91,50 +455,195 @@ fn build_challenge_id(correct_number: u8, secret: &[u8]) -> Result<(String, u64)
     Ok((format!("{nonce}:{timestamp}:{code}"), timestamp))
 }
 
-pub fn verify(secret: &[u8], challenge_id: &str, selected_index: u8, ttl: u64) -> bool {
-    let parts: Vec<&str> = challenge_id.split(':').collect();
-    if parts.len() != 3 {
-        return false;
+/// Components of a `nonce:timestamp:mac` challenge id.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParsedChallenge {
+    pub nonce: String,
+    pub timestamp: u64,
+    /// Raw HMAC-SHA256 bytes, base64-decoded.
+    pub mac: Vec<u8>,
+}
+
+impl FromStr for ParsedChallenge {
+    type Err = CaptchaError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split(':');
+        let (Some(nonce), Some(timestamp), Some(mac), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(CaptchaError::InvalidInput(
+                "challenge id must be nonce:timestamp:mac".into(),
+            ));
+        };
+
+        let timestamp = timestamp.parse().map_err(|_| {
+            CaptchaError::InvalidInput("challenge id timestamp is not a number".into())
+        })?;
+        let mac = BASE64_STANDARD.decode(mac).map_err(|_| {
+            CaptchaError::InvalidInput("challenge id mac is not valid base64".into())
+        })?;
+
+        Ok(Self {
+            nonce: nonce.to_string(),
+            timestamp,
+            mac,
+        })
     }
+}
 
-    let nonce = parts[0];
-    let timestamp: u64 = match parts[1].parse() {
-        Ok(t) => t,
-        Err(_) => return false,
-    };
-    let expected_code_b64 = parts[2];
+/// Bitmask of 1-based tile numbers, order and duplicates don't matter.
+/// `None` if a number falls outside `1..=64`.
+fn tiles_payload(numbers: &[u8]) -> Option<[u8; 8]> {
+    let mask = numbers.iter().try_fold(0u64, |mask, &n| {
+        let bit = n.checked_sub(1).and_then(|i| 1u64.checked_shl(i as u32))?;
+        Some(mask | bit)
+    })?;
 
-    let now = get_timestamp();
-    if now > timestamp.saturating_add(ttl) {
-        return false;
+    Some(mask.to_be_bytes())
+}
+
+/// Tagged so a dial angle never signs the same bytes as a tile mask.
+fn dial_payload(angle: u16) -> [u8; 6] {
+    let [hi, lo] = angle.to_be_bytes();
+    [b'd', b'i', b'a', b'l', hi, lo]
+}
+
+/// One-time key for the angle in a dial nonce, derived from the secret and
+/// the nonce's uuid. Without the secret the masked angle says nothing.
+fn dial_mask(secret: &[u8], uuid: &str) -> Option<u16> {
+    let mut mac = HmacSha256::new_from_slice(secret).ok()?;
+    mac.update(b"dial-mask");
+    mac.update(uuid.as_bytes());
+    let key = mac.finalize().into_bytes();
+
+    Some(u16::from_be_bytes([key[0], key[1]]))
+}
+
+/// Unmasks the angle carried in a dial `nonce`. Unauthenticated until the
+/// mac over its [`dial_payload`] is checked.
+fn dial_angle(secret: &[u8], nonce: &str) -> Option<u16> {
+    let (uuid, masked) = nonce.rsplit_once('.')?;
+    if masked.len() != 4 {
+        return None;
     }
+    let masked = u16::from_str_radix(masked, 16).ok()?;
 
+    Some(masked ^ dial_mask(secret, uuid)?)
+}
+
+/// Parses `challenge_id`, `None` if malformed or older than `ttl`.
+fn parse_unexpired(challenge_id: &str, ttl: u64, now: u64) -> Option<ParsedChallenge> {
+    let parsed: ParsedChallenge = challenge_id.parse().ok()?;
+    if now > parsed.timestamp.saturating_add(ttl) {
+        return None;
+    }
+
+    Some(parsed)
+}
+
+fn mac_matches(secret: &[u8], challenge: &ParsedChallenge, payload: &[u8]) -> bool {
     let mut mac = match HmacSha256::new_from_slice(secret) {
         Ok(m) => m,
         Err(_) => return false,
     };
-    mac.update(nonce.as_bytes());
-    mac.update(&[selected_index]);
-    mac.update(&timestamp.to_be_bytes());
+    mac.update(challenge.nonce.as_bytes());
+    mac.update(payload);
+    mac.update(&challenge.timestamp.to_be_bytes());
 
     let computed = mac.finalize().into_bytes();
 
-    let expected = match BASE64_STANDARD.decode(expected_code_b64) {
-        Ok(bytes) => bytes,
-        Err(_) => return false,
+    if challenge.mac.len() != computed.len() {
+        return false;
+    }
+
+    computed[..].ct_eq(challenge.mac.as_slice()).into()
+}
+
+/// Checks that `selected` is exactly the set of correct tiles.
+#[cfg(any(all(test, feature = "render"), feature = "test-utils"))]
+#[doc(hidden)]
+pub fn verify(secret: &[u8], challenge_id: &str, selected: &[u8], ttl: u64) -> bool {
+    verify_at(secret, challenge_id, selected, ttl, get_timestamp())
+}
+
+/// Same as [`verify`], checking expiry against `now` instead of the system clock.
+pub fn verify_at(secret: &[u8], challenge_id: &str, selected: &[u8], ttl: u64, now: u64) -> bool {
+    let Some(challenge) = parse_unexpired(challenge_id, ttl, now) else {
+        return false;
     };
 
-    if expected.len() != computed.len() {
+    // Out-of-range tiles still go through the HMAC so they take as long as
+    // any other wrong answer. An empty mask is never signed.
+    let payload = tiles_payload(selected);
+    let matches = mac_matches(secret, &challenge, &payload.unwrap_or_default());
+
+    payload.is_some() & matches
+}
+
+/// Widest accepted `tolerance_deg` for angle dial answers. Anything looser
+/// lets a random guess pass too often to be worth a challenge.
+pub const MAX_ANGLE_TOLERANCE_DEG: f32 = 45.0;
+
+/// Checks an [`ChallengeType::AngleDial`] answer: turning the tile
+/// clockwise by `submitted_angle_deg` must leave it within `tolerance_deg`
+/// of upright, wrapping around 360°. Tolerances above
+/// [`MAX_ANGLE_TOLERANCE_DEG`] never match.
+#[cfg(any(all(test, feature = "render"), feature = "test-utils"))]
+#[doc(hidden)]
+pub fn verify_angle(
+    secret: &[u8],
+    challenge_id: &str,
+    submitted_angle_deg: f32,
+    tolerance_deg: f32,
+    ttl: u64,
+) -> bool {
+    verify_angle_at(
+        secret,
+        challenge_id,
+        submitted_angle_deg,
+        tolerance_deg,
+        ttl,
+        get_timestamp(),
+    )
+}
+
+/// Same as [`verify_angle`], checking expiry against `now` instead of the system clock.
+pub fn verify_angle_at(
+    secret: &[u8],
+    challenge_id: &str,
+    submitted_angle_deg: f32,
+    tolerance_deg: f32,
+    ttl: u64,
+    now: u64,
+) -> bool {
+    if !submitted_angle_deg.is_finite()
+        || !tolerance_deg.is_finite()
+        || tolerance_deg > MAX_ANGLE_TOLERANCE_DEG
+    {
         return false;
     }
+    let Some(challenge) = parse_unexpired(challenge_id, ttl, now) else {
+        return false;
+    };
+
+    // Two HMACs whatever the answer: one to unmask the drawn angle, one to
+    // check it was signed. The tolerance check is plain arithmetic.
+    let Some(angle) = dial_angle(secret, &challenge.nonce) else {
+        return false;
+    };
+    // `rem_euclid` needs std, wrap negative remainders by hand for `no-std`.
+    let off = (angle as f32 + submitted_angle_deg) % 360.0;
+    let off = if off < 0.0 { off + 360.0 } else { off };
+    let within = off.min(360.0 - off) < tolerance_deg;
 
-    computed[..].ct_eq(expected.as_slice()).into()
+    mac_matches(secret, &challenge, &dial_payload(angle)) & within
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "render"))]
 mod tests {
     use super::*;
-    use crate::SpriteUri;
+    use crate::sprite::JpegSubsampling;
     use base64::engine::general_purpose;
     use std::collections::HashSet;
     use std::thread::sleep;
@@ -151,10 +660,16 @@ mod tests {
         let base = load_sample_image();
         let opts = GenerationOptions {
             cell_size: 150,
-            sprite_format: SpriteFormat::Jpeg { quality: 70 },
-            limits: None,
+            sprite_format: SpriteFormat::Jpeg {
+                quality: 70,
+                progressive: false,
+                chroma_subsampling: JpegSubsampling::Yuv420,
+            },
+            ..Default::default()
         };
-        generate::<SpriteUri>(&base, SECRET, &opts, NoiseOptions::default())
+        ChallengeBuilder::new(ChallengeType::Rotation)
+            .options(opts.clone())
+            .build::<SpriteUri>(&base, SECRET)
             .expect("Failed to generate challenge")
     }
 
@@ -164,7 +679,7 @@ mod tests {
         let result = verify(
             SECRET,
             &challenge.challenge_id,
-            challenge.correct_number,
+            &[challenge.correct_number],
             CHALLENGE_TTL,
         );
 
@@ -176,12 +691,55 @@ mod tests {
         assert!(result, "Challenge verification failed for correct index");
     }
 
+    #[test]
+    fn test_generation_options_compare_by_value() {
+        let opts = GenerationOptions::default();
+        assert_eq!(opts, GenerationOptions::default());
+        assert_ne!(
+            opts,
+            GenerationOptions {
+                sprite_format: SpriteFormat::Webp {
+                    quality: 70,
+                    method: 4,
+                },
+                ..Default::default()
+            }
+        );
+        assert_eq!(NoiseOptions::default(), NoiseOptions::default());
+        assert_ne!(NoiseOptions::default(), NoiseOptions::for_dark_background());
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_to_json_has_frontend_fields_only() {
+        let challenge = generate_challenge();
+        let json = challenge.to_json();
+
+        assert_eq!(json["challenge_id"], challenge.challenge_id.as_str());
+        assert!(
+            json["sprite"]
+                .as_str()
+                .unwrap()
+                .starts_with("data:image/jpeg")
+        );
+        assert_eq!(
+            json["expires_at"],
+            challenge.timestamp + CaptchaConfig::default().ttl_secs
+        );
+        assert_eq!(
+            (json["cols"].as_u64(), json["rows"].as_u64()),
+            (Some(3), Some(3))
+        );
+        assert_eq!(json.as_object().unwrap().len(), 5);
+        assert_eq!(serde_json::Value::from(challenge), json);
+    }
+
     #[test]
     fn test_verification_should_fail_for_wrong_guess() {
         let challenge = generate_challenge();
 
         let wrong = (challenge.correct_number + 1) % 9;
-        let valid = verify(SECRET, &challenge.challenge_id, wrong, 60);
+        let valid = verify(SECRET, &challenge.challenge_id, &[wrong], 60);
 
         assert!(!valid, "Verification should fail for wrong index");
     }
@@ -207,7 +765,12 @@ mod tests {
 
         sleep(Duration::from_secs(2));
 
-        let expired = verify(SECRET, &challenge.challenge_id, challenge.correct_number, 1);
+        let expired = verify(
+            SECRET,
+            &challenge.challenge_id,
+            &[challenge.correct_number],
+            1,
+        );
 
         assert!(!expired, "Expired challenge passed verification");
     }
@@ -217,11 +780,13 @@ mod tests {
         use std::time::Instant;
 
         let challenge = generate_challenge();
+        // The first call pays for cold caches whatever the answer
+        let _ = verify(SECRET, &challenge.challenge_id, &[1], 60);
 
         let mut durations = vec![];
         for i in 0..9 {
             let start = Instant::now();
-            let _ = verify(SECRET, &challenge.challenge_id, i, 60);
+            let _ = verify(SECRET, &challenge.challenge_id, &[i], 60);
             durations.push(start.elapsed().as_nanos());
         }
 
@@ -252,7 +817,7 @@ mod tests {
 
             for guess in 0..9 {
                 if guess != challenge.correct_number
-                    && verify(SECRET, &challenge.challenge_id, guess, 60)
+                    && verify(SECRET, &challenge.challenge_id, &[guess], 60)
                 {
                     false_positives += 1;
                 }
@@ -304,20 +869,161 @@ mod tests {
         let challenge = generate_challenge();
 
         let parts: Vec<&str> = challenge.challenge_id.split(':').collect();
-        let forged_index = (challenge.correct_number + 1) % 9;
+        let forged_index = challenge.correct_number % 9 + 1;
 
         // Recompute a forged HMAC for the wrong index
         let mut mac = hmac::Hmac::<Sha256>::new_from_slice(b"BAD_SECRET").unwrap();
         mac.update(parts[0].as_bytes());
-        mac.update(&[forged_index]);
+        mac.update(&(1u64 << (forged_index - 1)).to_be_bytes());
         mac.update(&parts[1].parse::<u64>().unwrap().to_be_bytes());
         let forged_code = general_purpose::STANDARD.encode(mac.finalize().into_bytes());
 
         let forged_challenge = format!("{}:{}:{}", parts[0], parts[1], forged_code);
-        let valid = verify(SECRET, &forged_challenge, forged_index, CHALLENGE_TTL);
+        let valid = verify(SECRET, &forged_challenge, &[forged_index], CHALLENGE_TTL);
         assert!(
             !valid,
             "Forged challenge ID was accepted. HMAC security failure"
         )
     }
+
+    #[test]
+    fn test_select_all_requires_exact_selection() {
+        let base = load_sample_image();
+        let opts = GenerationOptions {
+            cell_size: 64,
+            ..Default::default()
+        };
+        let challenge = ChallengeBuilder::new(ChallengeType::SelectAll { correct_count: 3 })
+            .options(opts)
+            .build::<SpriteBinary>(&base, SECRET)
+            .expect("Failed to generate select-all challenge");
+
+        let correct = challenge.correct_numbers.clone();
+        assert_eq!(correct.len(), 3);
+        assert_eq!(challenge.correct_number, correct[0]);
+
+        let id = &challenge.challenge_id;
+        let mut reversed = correct.clone();
+        reversed.reverse();
+        assert!(verify(SECRET, id, &correct, CHALLENGE_TTL));
+        assert!(verify(SECRET, id, &reversed, CHALLENGE_TTL));
+        assert!(!verify(SECRET, id, &correct[..2], CHALLENGE_TTL));
+
+        let extra = (1..=9).find(|n| !correct.contains(n)).unwrap();
+        let mut superset = correct.clone();
+        superset.push(extra);
+        assert!(!verify(SECRET, id, &superset, CHALLENGE_TTL));
+        assert!(!verify(SECRET, id, &[0], CHALLENGE_TTL));
+    }
+
+    #[test]
+    fn test_select_all_validates_correct_count() {
+        for correct_count in [0, 9] {
+            let err = ChallengeBuilder::new(ChallengeType::SelectAll { correct_count })
+                .build::<SpriteBinary>(&load_sample_image(), SECRET)
+                .err()
+                .expect("invalid correct_count accepted");
+            assert_eq!(
+                err,
+                CaptchaError::InvalidInput("correct_count must be between 1 and 8".into())
+            );
+        }
+    }
+
+    #[test]
+    fn test_verify_angle_wraps_around_upright() {
+        let (id, _) = sign_dial_id(350, SECRET, get_timestamp()).unwrap();
+
+        assert!(verify_angle(SECRET, &id, 10.0, 3.0, CHALLENGE_TTL));
+        assert!(verify_angle(SECRET, &id, 368.0, 3.0, CHALLENGE_TTL));
+        assert!(verify_angle(SECRET, &id, -349.0, 3.0, CHALLENGE_TTL));
+        assert!(!verify_angle(SECRET, &id, 14.0, 3.0, CHALLENGE_TTL));
+        assert!(!verify_angle(
+            SECRET,
+            &id,
+            f32::INFINITY,
+            3.0,
+            CHALLENGE_TTL
+        ));
+        assert!(!verify(SECRET, &id, &[1], CHALLENGE_TTL));
+        assert!(!verify_angle(SECRET, &id, 190.0, 180.0, CHALLENGE_TTL));
+    }
+
+    #[test]
+    fn test_dial_id_hides_and_binds_angle() {
+        let (a, _) = sign_dial_id(90, SECRET, get_timestamp()).unwrap();
+        let (b, _) = sign_dial_id(90, SECRET, get_timestamp()).unwrap();
+        let masked = |id: &str| {
+            let nonce = id.parse::<ParsedChallenge>().unwrap().nonce;
+            nonce.rsplit_once('.').unwrap().1.to_string()
+        };
+        assert_ne!(masked(&a), masked(&b));
+
+        // Flipping the masked angle changes what the mac is checked against
+        let parsed: ParsedChallenge = a.parse().unwrap();
+        let (uuid, hex) = parsed.nonce.rsplit_once('.').unwrap();
+        let flipped = u16::from_str_radix(hex, 16).unwrap() ^ 0x00b4;
+        let tampered = a.replacen(&parsed.nonce, &format!("{uuid}.{flipped:04x}"), 1);
+        assert!(verify_angle(SECRET, &a, 270.0, 3.0, CHALLENGE_TTL));
+        assert!(!verify_angle(SECRET, &tampered, 90.0, 3.0, CHALLENGE_TTL));
+        assert!(!verify_angle(SECRET, &tampered, 270.0, 3.0, CHALLENGE_TTL));
+    }
+
+    #[test]
+    fn test_save_sprite_writes_image_bytes() {
+        let base = load_sample_image();
+        let opts = GenerationOptions::default();
+        let dir = std::env::temp_dir();
+
+        let bin = ChallengeBuilder::new(ChallengeType::Rotation)
+            .options(opts.clone())
+            .build::<SpriteBinary>(&base, SECRET)
+            .expect("Failed to generate binary challenge");
+        let bin_path = dir.join(format!("geronimo-{}.jpg", Uuid::new_v4()));
+        bin.save_sprite(&bin_path).expect("Failed to save sprite");
+        assert_eq!(fs::read(&bin_path).unwrap(), bin.sprite.bytes);
+
+        let uri = generate_challenge();
+        let uri_path = dir.join(format!("geronimo-{}.jpg", Uuid::new_v4()));
+        uri.save_sprite_decoded(&uri_path)
+            .expect("Failed to save decoded sprite");
+        let saved = fs::read(&uri_path).unwrap();
+        assert!(uri.sprite.0.ends_with(&BASE64_STANDARD.encode(&saved)));
+
+        let _ = fs::remove_file(bin_path);
+        let _ = fs::remove_file(uri_path);
+    }
+
+    #[test]
+    fn test_parsed_challenge_from_str() {
+        let ch = generate_challenge();
+        let parsed: ParsedChallenge = ch.challenge_id.parse().expect("valid challenge id");
+        assert_eq!(parsed.timestamp, ch.timestamp);
+        assert!(ch.challenge_id.starts_with(&parsed.nonce));
+
+        for bad in ["", "a:b", "a:1:c:d", "a:not-a-number:AAAA", "a:1:!!!"] {
+            assert!(
+                matches!(
+                    bad.parse::<ParsedChallenge>(),
+                    Err(CaptchaError::InvalidInput(_))
+                ),
+                "{bad:?} should not parse"
+            );
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "render")))]
+mod verify_only_tests {
+    use super::*;
+
+    #[test]
+    fn test_build_and_verify_without_render() {
+        let (id, ts) = build_challenge_id_at(&[4], b"secret", 1_000).unwrap();
+
+        assert!(verify_at(b"secret", &id, &[4], 60, ts + 60));
+        assert!(!verify_at(b"secret", &id, &[5], 60, ts));
+        assert!(!verify_at(b"secret", &id, &[4], 60, ts + 61));
+        assert!(!verify_at(b"other", &id, &[4], 60, ts));
+    }
 }
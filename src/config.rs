@@ -0,0 +1,144 @@
+use crate::challenge::{ChallengeType, GenerationOptions};
+use crate::error::{CaptchaError, Result};
+use crate::image::{ColorJitter, NoiseLayer, NoiseOptions};
+use crate::sprite::{JpegSubsampling, SpriteFormat};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum SpriteEncoding {
+    #[default]
+    Jpeg,
+    Webp,
+    WebpLossless,
+}
+
+/// Tunable manager parameters, the shape of `from_env` variables
+/// and of config files read by `from_config_file`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct CaptchaConfig {
+    pub secret: String,
+    pub ttl_secs: u64,
+    pub cell_size: u32,
+    pub format: SpriteEncoding,
+    /// JPEG/WebP quality or lossless WebP compression level.
+    pub quality: u8,
+    pub progressive: bool,
+    /// Attach an in-memory registry with this attempts limit.
+    pub max_attempts: Option<u16>,
+    pub dark_background: bool,
+    /// Noise layers replacing the ones of the `dark_background` preset.
+    pub pre_noise: Option<NoiseLayer>,
+    pub post_noise: Option<NoiseLayer>,
+    pub challenge_type: ChallengeType,
+    pub cols: u32,
+    pub rows: u32,
+    pub max_cell_size: u32,
+    pub instruction_text: Option<String>,
+    pub draw_grid_lines: bool,
+    pub sharpen_after_resize: bool,
+    pub color_jitter: Option<ColorJitter>,
+    pub grayscale_tile_probability: f64,
+    pub perspective_warp: bool,
+}
+
+impl Default for CaptchaConfig {
+    fn default() -> Self {
+        let opts = GenerationOptions::default();
+
+        Self {
+            secret: String::new(),
+            ttl_secs: 300,
+            cell_size: 150,
+            format: SpriteEncoding::default(),
+            quality: 70,
+            progressive: false,
+            max_attempts: None,
+            dark_background: false,
+            pre_noise: None,
+            post_noise: None,
+            challenge_type: ChallengeType::default(),
+            cols: opts.cols,
+            rows: opts.rows,
+            max_cell_size: opts.max_cell_size,
+            instruction_text: opts.instruction_text,
+            draw_grid_lines: opts.draw_grid_lines,
+            sharpen_after_resize: opts.sharpen_after_resize,
+            color_jitter: opts.color_jitter,
+            grayscale_tile_probability: opts.grayscale_tile_probability,
+            perspective_warp: opts.perspective_warp,
+        }
+    }
+}
+
+impl CaptchaConfig {
+    pub(crate) fn sprite_format(&self) -> Result<SpriteFormat> {
+        let (range, format) = match self.format {
+            SpriteEncoding::Jpeg => (
+                1..=100,
+                SpriteFormat::Jpeg {
+                    quality: self.quality,
+                    progressive: self.progressive,
+                    chroma_subsampling: JpegSubsampling::Yuv420,
+                },
+            ),
+            SpriteEncoding::Webp => (
+                0..=100,
+                SpriteFormat::Webp {
+                    quality: self.quality,
+                    method: 4,
+                },
+            ),
+            SpriteEncoding::WebpLossless => (
+                0..=9,
+                SpriteFormat::WebpLossless {
+                    compression_level: self.quality,
+                },
+            ),
+        };
+
+        if !range.contains(&self.quality) {
+            return Err(CaptchaError::InvalidInput(format!(
+                "quality must be within {}..={} for {:?}",
+                range.start(),
+                range.end(),
+                self.format
+            )));
+        }
+
+        Ok(format)
+    }
+
+    pub(crate) fn generation_opts(&self) -> Result<GenerationOptions> {
+        Ok(GenerationOptions {
+            cell_size: self.cell_size,
+            sprite_format: self.sprite_format()?,
+            max_cell_size: self.max_cell_size,
+            instruction_text: self.instruction_text.clone(),
+            draw_grid_lines: self.draw_grid_lines,
+            cols: self.cols,
+            rows: self.rows,
+            sharpen_after_resize: self.sharpen_after_resize,
+            color_jitter: self.color_jitter,
+            grayscale_tile_probability: self.grayscale_tile_probability,
+            perspective_warp: self.perspective_warp,
+            ..Default::default()
+        })
+    }
+
+    pub(crate) fn noise(&self) -> NoiseOptions {
+        let preset = if self.dark_background {
+            NoiseOptions::for_dark_background()
+        } else {
+            NoiseOptions::default()
+        };
+
+        NoiseOptions {
+            pre_noise: self.pre_noise.unwrap_or(preset.pre_noise),
+            post_noise: self.post_noise.unwrap_or(preset.post_noise),
+            ..preset
+        }
+    }
+}
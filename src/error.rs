@@ -1,18 +1,74 @@
 use crate::registry::RegistryCheckResult;
+use alloc::string::String;
 use thiserror::Error;
 
-pub type Result<T> = std::result::Result<T, CaptchaError>;
+pub type Result<T> = core::result::Result<T, CaptchaError>;
 
 #[derive(Error, Debug)]
 pub enum CaptchaError {
     #[error("invalid input: {0}")]
     InvalidInput(String),
+    #[cfg(feature = "render")]
     #[error("decode image")]
     Decode(#[source] image::ImageError),
+    #[cfg(feature = "render")]
     #[error("encode image")]
     Encode(#[source] image::ImageError),
+    #[cfg(any(feature = "render", not(feature = "no-std")))]
+    #[error("io error")]
+    Io(#[source] std::io::Error),
     #[error("registry error: {0}")]
     Registry(RegistryCheckResult),
+    /// Caller-level throttling, as opposed to a [`CaptchaError::Registry`] rejection.
+    #[error("rate limited, retry after {retry_after_secs}s")]
+    RateLimit { retry_after_secs: u64 },
     #[error("internal error: {0}")]
     Internal(String),
 }
+
+/// Variants wrapping `image` or `io` errors never compare equal.
+impl PartialEq for CaptchaError {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::InvalidInput(a), Self::InvalidInput(b)) => a == b,
+            (Self::Registry(a), Self::Registry(b)) => a == b,
+            (
+                Self::RateLimit {
+                    retry_after_secs: a,
+                },
+                Self::RateLimit {
+                    retry_after_secs: b,
+                },
+            ) => a == b,
+            (Self::Internal(a), Self::Internal(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_eq_compares_comparable_variants_only() {
+        assert_eq!(
+            CaptchaError::RateLimit {
+                retry_after_secs: 5
+            },
+            CaptchaError::RateLimit {
+                retry_after_secs: 5
+            }
+        );
+        assert_ne!(
+            CaptchaError::InvalidInput("a".into()),
+            CaptchaError::Internal("a".into())
+        );
+
+        #[cfg(any(feature = "render", not(feature = "no-std")))]
+        {
+            let io = || CaptchaError::Io(std::io::Error::other("boom"));
+            assert_ne!(io(), io());
+        }
+    }
+}
@@ -1,20 +1,48 @@
-use crate::SpriteFormat;
+use crate::{CaptchaError, JpegSubsampling, SpriteFormat};
 
-use image::{DynamicImage, GenericImageView, Rgba};
-use imageproc::geometric_transformations::{Interpolation, rotate_about_center};
+use image::error::{EncodingError, ImageFormatHint};
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage, imageops};
+use imageproc::filter::filter3x3;
+use imageproc::geometric_transformations::{Interpolation, Projection, rotate_about_center, warp};
+use jpeg_encoder::{ColorType as JpegColorType, Encoder as JpegEncoder, SamplingFactor};
 use rand::Rng;
-use webp::Encoder as WebPEncoder;
+use rand_distr::{Distribution, Normal};
+use std::io::Write;
+use tracing::warn;
+use webp::{Encoder as WebPEncoder, WebPConfig};
 
-#[derive(Clone, Copy, Default)]
+/// (method, quality) per lossless compression level 0–9.
+const WEBP_LOSSLESS_PRESETS: [(i32, f32); 10] = [
+    (0, 0.0),
+    (1, 20.0),
+    (2, 25.0),
+    (3, 30.0),
+    (3, 50.0),
+    (4, 50.0),
+    (4, 75.0),
+    (4, 90.0),
+    (5, 90.0),
+    (6, 100.0),
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum NoisePattern {
     Dots,
     Lines,
     #[default]
     Grid,
+    /// Per-pixel sensor-like noise, `count` and `size` are ignored.
+    Gaussian {
+        std_dev: f32,
+    },
 }
 
-#[derive(Clone, Copy)]
-pub struct NoiseOptions {
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct NoiseLayer {
     pub count: u32,
     pub size: u32,
     pub blur_sigma: f32,
@@ -26,9 +54,9 @@ pub struct NoiseOptions {
     pub blue: bool,
 }
 
-impl Default for NoiseOptions {
+impl Default for NoiseLayer {
     fn default() -> Self {
-        NoiseOptions {
+        NoiseLayer {
             count: 300 * 9,
             size: 2,
             alpha: 100,
@@ -42,24 +70,330 @@ impl Default for NoiseOptions {
     }
 }
 
+impl NoiseLayer {
+    pub fn validate(&self) -> crate::Result<()> {
+        let (low, high) = self.color_range;
+        if low > high {
+            return Err(CaptchaError::InvalidInput(format!(
+                "noise color_range ({low}, {high}) must be ordered low to high"
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoiseOptions {
+    /// Applied to each rotated tile independently before compositing.
+    pub pre_noise: NoiseLayer,
+    /// Applied once to the full composite sprite.
+    pub post_noise: NoiseLayer,
+    pub background: Rgba<u8>,
+}
+
+impl Default for NoiseOptions {
+    fn default() -> Self {
+        NoiseOptions {
+            pre_noise: NoiseLayer {
+                count: 150,
+                blur_sigma: 0.0,
+                ..Default::default()
+            },
+            post_noise: NoiseLayer::default(),
+            background: Rgba([255, 255, 255, 255]),
+        }
+    }
+}
+
+impl NoiseOptions {
+    pub fn validate(&self) -> crate::Result<()> {
+        self.pre_noise.validate()?;
+        self.post_noise.validate()
+    }
+
+    /// Preset for dark UI themes: dark sprite background
+    /// and noise biased towards light colors.
+    pub fn for_dark_background() -> Self {
+        let defaults = NoiseOptions::default();
+
+        NoiseOptions {
+            pre_noise: NoiseLayer {
+                color_range: (140, 255),
+                ..defaults.pre_noise
+            },
+            post_noise: NoiseLayer {
+                color_range: (140, 255),
+                ..defaults.post_noise
+            },
+            background: Rgba([24, 24, 27, 255]),
+        }
+    }
+}
+
+/// Per-tile colour augmentation. Each field is the largest deviation drawn
+/// for a tile, e.g. `brightness: 0.1` shifts every tile by up to ±10%.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ColorJitter {
+    pub brightness: f32,
+    pub contrast: f32,
+    pub saturation: f32,
+}
+
+impl ColorJitter {
+    pub fn validate(&self) -> crate::Result<()> {
+        for (name, value) in [
+            ("brightness", self.brightness),
+            ("contrast", self.contrast),
+            ("saturation", self.saturation),
+        ] {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(CaptchaError::InvalidInput(format!(
+                    "color jitter {name} must be between 0 and 1"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Draws a concrete shift within `±self` for one tile.
+    pub(crate) fn sample<R: Rng>(&self, rng: &mut R) -> ColorJitter {
+        let mut pick = |max: f32| {
+            if max > 0.0 {
+                rng.random_range(-max..=max)
+            } else {
+                0.0
+            }
+        };
+
+        ColorJitter {
+            brightness: pick(self.brightness),
+            contrast: pick(self.contrast),
+            saturation: pick(self.saturation),
+        }
+    }
+
+    /// Applies a shift drawn by [`ColorJitter::sample`], alpha is untouched.
+    pub(crate) fn apply(&self, img: &mut RgbaImage) {
+        if self.brightness != 0.0 {
+            imageops::colorops::brighten_in_place(img, (self.brightness * 255.0) as i32);
+        }
+        if self.contrast != 0.0 {
+            imageops::colorops::contrast_in_place(img, self.contrast * 100.0);
+        }
+        if self.saturation != 0.0 {
+            let factor = 1.0 + self.saturation;
+            for px in img.pixels_mut() {
+                let luma = 0.299 * px[0] as f32 + 0.587 * px[1] as f32 + 0.114 * px[2] as f32;
+                for c in 0..3 {
+                    px[c] = (luma + (px[c] as f32 - luma) * factor).clamp(0.0, 255.0) as u8;
+                }
+            }
+        }
+    }
+}
+
+impl SpriteFormat {
+    /// Detect JPEG or WebP from magic bytes, using default encoder
+    /// settings for the returned variant.
+    pub fn detect_from_bytes(buf: &[u8]) -> Option<SpriteFormat> {
+        if buf.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            return Some(SpriteFormat::default());
+        }
+
+        if buf.len() >= 16 && &buf[0..4] == b"RIFF" && &buf[8..12] == b"WEBP" {
+            return Some(match &buf[12..16] {
+                b"VP8L" => SpriteFormat::WebpLossless {
+                    compression_level: 6,
+                },
+                _ => SpriteFormat::Webp {
+                    quality: 70,
+                    method: 4,
+                },
+            });
+        }
+
+        None
+    }
+}
+
 /// Rotate image by arbitrary angle
 /// using imageproc (nearest-neighbor).
-pub fn rotate_image(img: &DynamicImage, angle_deg: f32) -> DynamicImage {
+/// Uncovered corners are filled with `bg`.
+pub fn rotate_image(img: &DynamicImage, angle_deg: f32, bg: Rgba<u8>) -> DynamicImage {
     if angle_deg == 0.0 {
         return img.clone();
     }
 
     let rgba = img.to_rgba8();
-    let bg = Rgba([255, 255, 255, 255]);
     let rotated = rotate_about_center(&rgba, angle_deg.to_radians(), Interpolation::Nearest, bg);
 
     DynamicImage::ImageRgba8(rotated)
 }
 
-pub fn watermark_with_noise(img: &mut DynamicImage, opts: NoiseOptions) {
-    let mut rng = rand::rng();
-    let (width, height) = img.dimensions();
+/// Maps the corners of `img` onto `corners` (top-left, top-right,
+/// bottom-right, bottom-left, in pixels). Uncovered areas are filled with `bg`.
+pub fn perspective_warp(img: &RgbaImage, corners: [(f32, f32); 4], bg: Rgba<u8>) -> RgbaImage {
+    let (w, h) = (img.width() as f32, img.height() as f32);
+    let from = [(0.0, 0.0), (w, 0.0), (w, h), (0.0, h)];
+
+    match Projection::from_control_points(from, corners) {
+        Some(projection) => warp(img, &projection, Interpolation::Bilinear, bg),
+        None => img.clone(),
+    }
+}
+
+/// Composites an image with an alpha channel over an opaque `bg`.
+pub fn flatten_alpha(img: DynamicImage, bg: Rgba<u8>) -> DynamicImage {
+    if !img.color().has_alpha() {
+        return img;
+    }
+
+    let mut rgba = img.into_rgba8();
+    for px in rgba.pixels_mut() {
+        let a = px[3] as u16;
+        for c in 0..3 {
+            px[c] = ((px[c] as u16 * a + bg[c] as u16 * (255 - a) + 127) / 255) as u8;
+        }
+        px[3] = 255;
+    }
+
+    DynamicImage::ImageRgba8(rgba)
+}
+
+/// 3x3 identity-minus-laplacian sharpen, per channel.
+pub fn sharpen(img: &DynamicImage) -> DynamicImage {
+    const KERNEL: [i32; 9] = [0, -1, 0, -1, 5, -1, 0, -1, 0];
+    DynamicImage::ImageRgba8(filter3x3::<_, i32, u8>(&img.to_rgba8(), &KERNEL))
+}
+
+pub fn watermark_with_noise(img: &mut DynamicImage, opts: NoiseLayer) {
+    if let Some(img_buf) = img.as_mut_rgba8() {
+        apply_noise(img_buf, &opts, &mut rand::rng());
+        return;
+    }
+
     let mut img_buf = img.to_rgba8();
+    apply_noise(&mut img_buf, &opts, &mut rand::rng());
+
+    *img = DynamicImage::ImageRgba8(img_buf);
+}
+
+pub fn apply_noise<R: Rng>(img_buf: &mut RgbaImage, opts: &NoiseLayer, rng: &mut R) {
+    if let NoisePattern::Gaussian { std_dev } = opts.shape {
+        add_gaussian_noise(img_buf, opts, std_dev, rng);
+    } else {
+        scatter_noise(img_buf, opts, rng);
+    }
+
+    if opts.blur_sigma > 0.0 {
+        #[cfg(feature = "simd")]
+        {
+            *img_buf = fast_blur_simd(img_buf, opts.blur_sigma);
+        }
+        #[cfg(not(feature = "simd"))]
+        {
+            *img_buf = image::imageops::fast_blur(img_buf, opts.blur_sigma);
+        }
+    }
+}
+
+/// Vectorised drop-in for [`image::imageops::fast_blur`] on RGBA images: three
+/// box-blur passes per axis, with the four channels of a pixel summed in
+/// one `f32x4` lane group. Output may differ from the reference by ±1.
+#[cfg(feature = "simd")]
+pub fn fast_blur_simd(img: &RgbaImage, sigma: f32) -> RgbaImage {
+    let (width, height) = img.dimensions();
+    let boxes = boxes_for_gauss(sigma, 3);
+    if width == 0 || height == 0 || boxes.is_empty() {
+        return img.clone();
+    }
+
+    let (w, h) = (width as usize, height as usize);
+    let mut buf = img.as_raw().clone();
+    let mut transient = vec![0u8; buf.len()];
+
+    for radius in boxes {
+        box_blur_pass(&buf, &mut transient, w, h, 1, w, radius);
+        box_blur_pass(&transient, &mut buf, h, w, w, 1, radius);
+    }
+
+    RgbaImage::from_raw(width, height, buf).expect("buffer size matches dimensions")
+}
+
+/// Box radii approximating a gaussian of `sigma` with `n` passes, same as
+/// the `image` crate (Kovesi, DICTA 2010).
+#[cfg(feature = "simd")]
+fn boxes_for_gauss(sigma: f32, n: usize) -> Vec<usize> {
+    let w_ideal = f32::sqrt((12.0 * sigma.powi(2) / (n as f32)) + 1.0);
+    let mut w_l = w_ideal.floor();
+    if w_l % 2.0 == 0.0 {
+        w_l -= 1.0;
+    }
+    let w_u = w_l + 2.0;
+
+    let m_ideal = 0.25 * (n as f32) * (w_l + 3.0) - 3.0 * sigma.powi(2) * (w_l + 1.0).recip();
+    let m = f32::round(m_ideal) as usize;
+
+    (0..n)
+        .map(|i| if i < m { w_l as usize } else { w_u as usize })
+        .map(|i| (i.saturating_sub(1) / 2) | 1)
+        .collect()
+}
+
+/// Sliding-window box blur along `lines` lines of `len` pixels, where
+/// consecutive pixels are `step` apart and lines start `line_stride` apart
+/// (both in pixels). Edges are clamped.
+#[cfg(feature = "simd")]
+fn box_blur_pass(
+    src: &[u8],
+    dst: &mut [u8],
+    len: usize,
+    lines: usize,
+    step: usize,
+    line_stride: usize,
+    radius: usize,
+) {
+    use wide::f32x4;
+
+    let load = |px: usize| {
+        let p = &src[px * 4..px * 4 + 4];
+        f32x4::new([p[0] as f32, p[1] as f32, p[2] as f32, p[3] as f32])
+    };
+    let weight = f32x4::splat(1.0 / (radius * 2 + 1) as f32);
+    let (lo, hi) = (f32x4::splat(0.0), f32x4::splat(255.0));
+
+    for line in 0..lines {
+        let at = |x: usize| line * line_stride + x.min(len - 1) * step;
+
+        let mut sum = load(at(0)) * f32x4::splat((radius + 1) as f32);
+        for x in 1..=radius {
+            sum += load(at(x));
+        }
+
+        for x in 0..len {
+            let out = (sum * weight).round().max(lo).min(hi).to_array();
+            let px = at(x) * 4;
+            dst[px..px + 4].copy_from_slice(&out.map(|c| c as u8));
+
+            sum += load(at(x + radius + 1));
+            sum -= load(at(x.saturating_sub(radius)));
+        }
+    }
+}
+
+fn scatter_noise<R: Rng>(img_buf: &mut RgbaImage, opts: &NoiseLayer, rng: &mut R) {
+    let (width, height) = img_buf.dimensions();
+    if width == 0 || height == 0 {
+        return;
+    }
+    if let Err(e) = opts.validate() {
+        warn!("skipping noise layer: {e}");
+        return;
+    }
 
     for _ in 0..opts.count {
         let x = rng.random_range(0..width);
@@ -103,43 +437,385 @@ pub fn watermark_with_noise(img: &mut DynamicImage, opts: NoiseOptions) {
                     }
                 }
             }
+            // Applied per pixel in add_gaussian_noise
+            NoisePattern::Gaussian { .. } => {}
         }
     }
+}
 
-    *img = DynamicImage::ImageRgba8(img_buf);
+fn add_gaussian_noise<R: Rng>(
+    img_buf: &mut RgbaImage,
+    opts: &NoiseLayer,
+    std_dev: f32,
+    rng: &mut R,
+) {
+    if !std_dev.is_finite() || std_dev <= 0.0 {
+        return;
+    }
 
-    if opts.blur_sigma > 0.0 {
-        *img = img.fast_blur(opts.blur_sigma);
+    let Ok(normal) = Normal::new(0.0f32, std_dev) else {
+        return;
+    };
+
+    let channels = [opts.red, opts.green, opts.blue];
+
+    for pixel in img_buf.pixels_mut() {
+        for (value, _) in pixel.0.iter_mut().zip(channels).filter(|(_, on)| *on) {
+            let noisy = *value as f32 + normal.sample(rng);
+            *value = noisy.round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// Detects a base image's format from its magic bytes, rejecting anything
+/// but JPEG, PNG or WebP before it reaches a decoder.
+pub(crate) fn validate_image_format(buf: &[u8]) -> crate::Result<ImageFormat> {
+    match image::guess_format(buf) {
+        Ok(format @ (ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP)) => Ok(format),
+        Ok(other) => Err(CaptchaError::InvalidInput(format!(
+            "expected JPEG, PNG or WebP image, got {}",
+            format!("{other:?}").to_uppercase()
+        ))),
+        Err(_) => Err(CaptchaError::InvalidInput(
+            "expected JPEG, PNG or WebP image, got unrecognized data".into(),
+        )),
     }
 }
 
+/// Encode `img` into a new buffer, returning the bytes with their mime type.
 pub fn encode_image(
     img: &DynamicImage,
     fmt: &SpriteFormat,
 ) -> Result<(Vec<u8>, &'static str), image::ImageError> {
-    match *fmt {
-        SpriteFormat::Jpeg { quality } => {
-            let mut buf = Vec::new();
-            let mut enc = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buf, quality);
+    let mut buf = Vec::new();
+    encode_image_to_writer(img, fmt, &mut buf)?;
+
+    Ok((buf, fmt.mime_type()))
+}
 
+/// Encode straight into `writer`. JPEG is streamed as it is encoded,
+/// WebP is written from libwebp's output buffer.
+pub fn encode_image_to_writer<W: Write>(
+    img: &DynamicImage,
+    fmt: &SpriteFormat,
+    writer: &mut W,
+) -> Result<(), image::ImageError> {
+    match *fmt {
+        SpriteFormat::Jpeg {
+            quality,
+            progressive,
+            chroma_subsampling,
+        } => {
             let rgb = img.to_rgb8();
-            let dyn_rgb = image::DynamicImage::ImageRgb8(rgb);
+            let (width, height) = match (u16::try_from(rgb.width()), u16::try_from(rgb.height())) {
+                (Ok(w), Ok(h)) => (w, h),
+                _ => {
+                    return Err(encoding_error(
+                        ImageFormat::Jpeg,
+                        "image dimensions exceed JPEG limits",
+                    ));
+                }
+            };
+
+            let mut enc = JpegEncoder::new(writer, quality.clamp(1, 100));
+            enc.set_progressive(progressive);
+            enc.set_sampling_factor(match chroma_subsampling {
+                JpegSubsampling::Yuv420 => SamplingFactor::F_2_2,
+                JpegSubsampling::Yuv444 => SamplingFactor::F_1_1,
+            });
+            enc.encode(rgb.as_raw(), width, height, JpegColorType::Rgb)
+                .map_err(|e| encoding_error(ImageFormat::Jpeg, e))
+        }
+        SpriteFormat::Webp { quality, method } => {
+            let rgba = img.to_rgba8();
+            let enc = WebPEncoder::from_rgba(rgba.as_raw(), rgba.width(), rgba.height());
 
-            enc.encode_image(&dyn_rgb)?;
+            let mut config = WebPConfig::new()
+                .map_err(|_| encoding_error(ImageFormat::WebP, "init encoder config"))?;
+            config.quality = quality as f32;
+            config.method = i32::from(method.min(6));
 
-            Ok((buf, "image/jpeg"))
+            let webp = enc
+                .encode_advanced(&config)
+                .map_err(|e| encoding_error(ImageFormat::WebP, format!("{e:?}")))?;
+
+            writer.write_all(&webp).map_err(image::ImageError::IoError)
         }
-        SpriteFormat::Webp { quality, lossless } => {
+        SpriteFormat::WebpLossless { compression_level } => {
             let rgba = img.to_rgba8();
             let enc = WebPEncoder::from_rgba(rgba.as_raw(), rgba.width(), rgba.height());
 
-            let webp = if lossless {
-                enc.encode_lossless()
-            } else {
-                enc.encode(quality as f32)
+            // Same mapping as libwebp's WebPConfigLosslessPreset
+            let (method, quality) = WEBP_LOSSLESS_PRESETS[compression_level.min(9) as usize];
+            let mut config = WebPConfig::new()
+                .map_err(|_| encoding_error(ImageFormat::WebP, "init encoder config"))?;
+            config.lossless = 1;
+            config.alpha_compression = 0;
+            config.method = method;
+            config.quality = quality;
+
+            let webp = enc
+                .encode_advanced(&config)
+                .map_err(|e| encoding_error(ImageFormat::WebP, format!("{e:?}")))?;
+
+            writer.write_all(&webp).map_err(image::ImageError::IoError)
+        }
+    }
+}
+
+fn encoding_error(
+    format: ImageFormat,
+    err: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+) -> image::ImageError {
+    image::ImageError::Encoding(EncodingError::new(ImageFormatHint::Exact(format), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn gray_tile() -> RgbaImage {
+        RgbaImage::from_pixel(32, 32, Rgba([128, 128, 128, 255]))
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_noise_pattern_json_roundtrip() {
+        let patterns = [
+            (NoisePattern::Dots, r#""dots""#),
+            (NoisePattern::Lines, r#""lines""#),
+            (NoisePattern::Grid, r#""grid""#),
+            (
+                NoisePattern::Gaussian { std_dev: 1.5 },
+                r#"{"gaussian":{"std_dev":1.5}}"#,
+            ),
+        ];
+
+        for (pattern, json) in patterns {
+            assert_eq!(serde_json::to_string(&pattern).unwrap(), json);
+            assert_eq!(serde_json::from_str::<NoisePattern>(json).unwrap(), pattern);
+        }
+    }
+
+    #[test]
+    fn test_color_jitter_full_desaturation_is_gray() {
+        let mut img = RgbaImage::from_pixel(4, 4, Rgba([200, 40, 90, 255]));
+        let shift = ColorJitter {
+            saturation: -1.0,
+            ..Default::default()
+        };
+        shift.apply(&mut img);
+
+        let px = img.get_pixel(0, 0).0;
+        assert_eq!(px[0], px[1]);
+        assert_eq!(px[1], px[2]);
+        assert_eq!(px[3], 255);
+
+        let jitter = ColorJitter {
+            brightness: 1.5,
+            ..Default::default()
+        };
+        assert!(jitter.validate().is_err());
+    }
+
+    #[test]
+    fn test_perspective_warp_fills_uncovered_corners() {
+        let img = gray_tile();
+        let bg = Rgba([255, 0, 0, 255]);
+        let out = perspective_warp(
+            &img,
+            [(4.0, 4.0), (32.0, 0.0), (32.0, 32.0), (0.0, 32.0)],
+            bg,
+        );
+
+        assert_eq!(out.dimensions(), img.dimensions());
+        assert_eq!(out.get_pixel(0, 0), &bg);
+        assert_eq!(out.get_pixel(16, 16).0, [128, 128, 128, 255]);
+    }
+
+    #[test]
+    fn test_sharpen_boosts_edge_contrast() {
+        let mut img = gray_tile();
+        for y in 0..32 {
+            for x in 16..32 {
+                img.put_pixel(x, y, Rgba([160, 160, 160, 255]));
+            }
+        }
+
+        let out = sharpen(&DynamicImage::ImageRgba8(img)).to_rgba8();
+        assert!(out.get_pixel(15, 8).0[0] < 128);
+        assert!(out.get_pixel(16, 8).0[0] > 160);
+        assert_eq!(out.get_pixel(4, 8).0, [128, 128, 128, 255]);
+    }
+
+    #[test]
+    fn test_gaussian_noise_perturbs_enabled_channels_only() {
+        let mut img = gray_tile();
+        let opts = NoiseLayer {
+            shape: NoisePattern::Gaussian { std_dev: 20.0 },
+            blur_sigma: 0.0,
+            blue: false,
+            ..Default::default()
+        };
+
+        apply_noise(&mut img, &opts, &mut rand::rng());
+
+        assert!(img.pixels().any(|p| p.0[0] != 128 || p.0[1] != 128));
+        assert!(img.pixels().all(|p| p.0[2] == 128 && p.0[3] == 255));
+    }
+
+    #[test]
+    fn test_detect_sprite_format_from_magic_bytes() {
+        let jpeg = include_bytes!("../assets/sample1.jpg");
+        assert!(matches!(
+            SpriteFormat::detect_from_bytes(jpeg),
+            Some(SpriteFormat::Jpeg { .. })
+        ));
+
+        let img = DynamicImage::ImageRgba8(gray_tile());
+        let (lossy, _) = encode_image(
+            &img,
+            &SpriteFormat::Webp {
+                quality: 50,
+                method: 4,
+            },
+        )
+        .unwrap();
+        assert!(matches!(
+            SpriteFormat::detect_from_bytes(&lossy),
+            Some(SpriteFormat::Webp { .. })
+        ));
+
+        let fmt = SpriteFormat::WebpLossless {
+            compression_level: 1,
+        };
+        let (lossless, _) = encode_image(&img, &fmt).unwrap();
+        assert!(matches!(
+            SpriteFormat::detect_from_bytes(&lossless),
+            Some(SpriteFormat::WebpLossless { .. })
+        ));
+
+        assert!(SpriteFormat::detect_from_bytes(b"\x89PNG\r\n\x1a\n").is_none());
+        assert!(SpriteFormat::detect_from_bytes(b"RIFF").is_none());
+    }
+
+    #[test]
+    fn test_webp_method_is_clamped() {
+        let img = DynamicImage::ImageRgba8(gray_tile());
+        for method in [0, 6, u8::MAX] {
+            let fmt = SpriteFormat::Webp {
+                quality: 70,
+                method,
+            };
+            let (buf, _) = encode_image(&img, &fmt).unwrap();
+            let decoded = image::load_from_memory(&buf).unwrap();
+            assert_eq!(
+                (decoded.width(), decoded.height()),
+                (img.width(), img.height())
+            );
+        }
+    }
+
+    #[test]
+    fn test_encode_to_writer_matches_buffered_encode() {
+        let img = DynamicImage::ImageRgba8(gray_tile());
+        let fmt = SpriteFormat::default();
+
+        let (buffered, _) = encode_image(&img, &fmt).unwrap();
+        let mut streamed = Cursor::new(Vec::new());
+        encode_image_to_writer(&img, &fmt, &mut streamed).unwrap();
+
+        assert_eq!(streamed.into_inner(), buffered);
+    }
+
+    #[test]
+    fn test_jpeg_yuv444_keeps_more_colour_detail() {
+        let mut rng = rand::rng();
+        let mut noisy = RgbaImage::new(64, 64);
+        noisy.pixels_mut().for_each(|px| *px = Rgba(rng.random()));
+        let img = DynamicImage::ImageRgba8(noisy);
+
+        let encode = |chroma_subsampling| {
+            let fmt = SpriteFormat::Jpeg {
+                quality: 80,
+                progressive: false,
+                chroma_subsampling,
             };
+            encode_image(&img, &fmt).unwrap().0
+        };
+        let yuv420 = encode(JpegSubsampling::Yuv420);
+        let yuv444 = encode(JpegSubsampling::Yuv444);
+
+        assert!(yuv444.len() > yuv420.len());
+        assert!(image::load_from_memory(&yuv444).is_ok());
+    }
 
-            Ok((webp.to_vec(), "image/webp"))
+    #[cfg(feature = "simd")]
+    #[test]
+    fn test_fast_blur_simd_matches_reference() {
+        let mut rng = rand::rng();
+        let mut img = RgbaImage::new(37, 23);
+        for px in img.pixels_mut() {
+            *px = Rgba(rng.random());
         }
+
+        for sigma in [0.7, 2.0, 9.5] {
+            let reference = image::imageops::fast_blur(&img, sigma);
+            let simd = fast_blur_simd(&img, sigma);
+
+            let max_diff = reference
+                .as_raw()
+                .iter()
+                .zip(simd.as_raw())
+                .map(|(a, b)| a.abs_diff(*b))
+                .max();
+            assert!(max_diff <= Some(1), "sigma {sigma}: max diff {max_diff:?}");
+        }
+    }
+
+    #[test]
+    fn test_reversed_color_range_is_rejected_without_panic() {
+        let layer = NoiseLayer {
+            color_range: (255, 0),
+            ..Default::default()
+        };
+        assert!(matches!(
+            layer.validate(),
+            Err(CaptchaError::InvalidInput(_))
+        ));
+
+        let mut img = gray_tile();
+        let before = img.clone();
+        apply_noise(
+            &mut img,
+            &NoiseLayer {
+                blur_sigma: 0.0,
+                ..layer
+            },
+            &mut rand::rng(),
+        );
+        assert_eq!(img, before);
+
+        let noise = NoiseOptions {
+            post_noise: layer,
+            ..Default::default()
+        };
+        assert!(noise.validate().is_err());
+        assert!(NoiseOptions::for_dark_background().validate().is_ok());
+    }
+
+    #[test]
+    fn test_gaussian_noise_invalid_std_dev_is_noop() {
+        let mut img = gray_tile();
+        let opts = NoiseLayer {
+            shape: NoisePattern::Gaussian { std_dev: -1.0 },
+            blur_sigma: 0.0,
+            ..Default::default()
+        };
+
+        apply_noise(&mut img, &opts, &mut rand::rng());
+
+        assert_eq!(img, gray_tile());
     }
 }
@@ -1,14 +1,90 @@
+#![cfg_attr(all(feature = "no-std", not(feature = "render"), not(test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "actix")]
+mod actix;
+#[cfg(feature = "axum")]
+mod axum;
+#[cfg(feature = "render")]
+mod cache;
 mod challenge;
+#[cfg(feature = "render")]
+mod config;
 mod error;
+#[cfg(feature = "render")]
 mod image;
+#[cfg(feature = "render")]
 mod manager;
+#[cfg(feature = "render")]
+mod pool;
 mod registry;
+#[cfg(feature = "render")]
 mod sprite;
+#[cfg(all(feature = "render", any(test, feature = "test-utils")))]
+mod testing;
 mod utils;
 
-pub use challenge::{CaptchaChallenge, GenerationOptions};
+#[cfg(feature = "render")]
+pub use cache::RotatedImageCache;
+#[cfg(feature = "test-utils")]
+#[doc(hidden)]
+pub use cache::SpriteCache;
+#[cfg(feature = "render")]
+pub use challenge::{
+    CaptchaChallenge, ChallengeBuilder, GenerationOptions, MAX_GRAYSCALE_TILE_PROBABILITY,
+    MIN_CELL_SIZE,
+};
+pub use challenge::{ChallengeType, MAX_ANGLE_TOLERANCE_DEG, ParsedChallenge};
+#[cfg(feature = "test-utils")]
+#[doc(hidden)]
+pub use challenge::{build_challenge_id, verify, verify_angle};
+// Verify-only builds, e.g. for wasm32-unknown-unknown, expose the challenge
+// id helpers directly. Prefer the `_at` variants there, SystemTime panics on
+// targets without a system clock and doesn't exist under `no-std`.
+#[cfg(not(any(feature = "render", feature = "no-std")))]
+pub use challenge::build_challenge_id;
+#[cfg(not(feature = "render"))]
+pub use challenge::{build_challenge_id_at, verify_angle_at, verify_at};
+#[cfg(feature = "render")]
+pub use config::{CaptchaConfig, SpriteEncoding};
 pub use error::{CaptchaError, Result};
-pub use image::NoiseOptions;
+#[cfg(feature = "render")]
+pub use image::{ColorJitter, NoiseLayer, NoiseOptions, NoisePattern};
+#[cfg(feature = "render")]
 pub use manager::CaptchaManager;
-pub use registry::{ChallengeInMemoryRegistry, ChallengeRegistry, RegistryCheckResult};
-pub use sprite::{SpriteBinary, SpriteFormat, SpriteUri};
+#[cfg(feature = "render")]
+pub use pool::SpriteBufferPool;
+#[cfg(feature = "render")]
+pub use registry::ChallengeInMemoryRegistry;
+#[cfg(all(feature = "render", any(debug_assertions, feature = "debug-utils")))]
+pub use registry::ChallengeSnapshot;
+pub use registry::{ChallengeRegistry, RegistryCheckResult};
+#[cfg(feature = "tokio")]
+pub use sprite::SpriteFile;
+#[cfg(feature = "mmap")]
+pub use sprite::create_sprite_from_path;
+#[cfg(feature = "render")]
+pub use sprite::{
+    JpegSubsampling, SpriteBinary, SpriteFormat, SpriteUri, add_instruction_banner,
+    create_sprite_from_image, highlight_tile,
+};
+#[cfg(feature = "test-utils")]
+#[doc(hidden)]
+pub use sprite::{create_sprite, create_sprite_seeded};
+#[cfg(feature = "test-utils")]
+pub use testing::{
+    FailingChallengeRegistry, MockChallengeRegistry, MockClock, MockRegistryBuilder,
+};
+pub use utils::Clock;
+#[cfg(any(feature = "render", not(feature = "no-std")))]
+pub use utils::SystemClock;
+
+#[cfg(feature = "render")]
+pub mod image_utils {
+    #[cfg(feature = "simd")]
+    pub use crate::image::fast_blur_simd;
+    pub use crate::image::{
+        encode_image, encode_image_to_writer, rotate_image, watermark_with_noise,
+    };
+}
@@ -1,13 +1,28 @@
+use crate::RegistryCheckResult;
+use crate::cache::RotatedImageCache;
+use crate::challenge::{self, ChallengeBuilder, ChallengeType, MAX_ANGLE_TOLERANCE_DEG};
+use crate::config::CaptchaConfig;
 use crate::error::{CaptchaError, Result};
 use crate::image::NoiseOptions;
-use crate::registry::ChallengeRegistry;
+#[cfg(any(test, feature = "reqwest", feature = "notify"))]
+use crate::image::validate_image_format;
+use crate::pool::SpriteBufferPool;
+use crate::registry::{ChallengeInMemoryRegistry, ChallengeRegistry};
 use crate::sprite::{SpriteFormat, SpriteTarget};
-use crate::{RegistryCheckResult, challenge};
+use crate::utils::{Clock, SystemClock};
 
-use rand::prelude::IndexedRandom;
-use rand::rng;
-use std::sync::Arc;
-use tracing::{info, warn};
+use rand::{Rng, RngCore, rng};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::env::{self, VarError};
+use std::fmt::{self, Display};
+#[cfg(any(feature = "toml", feature = "serde_json", feature = "notify"))]
+use std::fs;
+#[cfg(any(feature = "toml", feature = "serde_json", feature = "notify"))]
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use tracing::{debug, info, warn};
 use zeroize::Zeroizing;
 
 pub const SAMPLE_IMAGES: &[&[u8]] = &[
@@ -20,12 +35,84 @@ pub const SAMPLE_IMAGES: &[&[u8]] = &[
     include_bytes!("../assets/sample7.jpg"),
 ];
 
+const SECRET_ENV: &str = "CAPTCHA_SECRET";
+
+/// Base images added at runtime, keyed by where they were loaded from, with
+/// their rotation cache hash.
+type BaseImages = RwLock<Vec<(String, Arc<[u8]>, u64)>>;
+
+/// Issues and verifies challenges. All state is behind `Arc`s and internally
+/// synchronized, so the manager is `Send + Sync` and cheap to clone: share it
+/// across tasks directly or through clones, no `Mutex` needed.
+#[derive(Clone)]
 pub struct CaptchaManager {
     registry: Option<Arc<dyn ChallengeRegistry>>,
     challenge_ttl: u64,
     noise: NoiseOptions,
-    secret: Zeroizing<Vec<u8>>,
+    secret: Arc<Zeroizing<Vec<u8>>>,
     gen_opts: challenge::GenerationOptions,
+    challenge_type: ChallengeType,
+    rotations: Arc<RotatedImageCache>,
+    /// Rotation cache hashes of `SAMPLE_IMAGES`.
+    sample_hashes: Arc<[u64]>,
+    buffers: Arc<SpriteBufferPool>,
+    clock: Arc<dyn Clock>,
+    images: Arc<BaseImages>,
+}
+
+#[cfg(test)]
+static_assertions::assert_impl_all!(CaptchaManager: Send, Sync);
+
+impl fmt::Debug for CaptchaManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CaptchaManager")
+            .field("secret", &"[REDACTED]")
+            .field("challenge_ttl", &self.challenge_ttl)
+            .field("noise", &self.noise)
+            .field("gen_opts", &self.gen_opts)
+            .field("challenge_type", &self.challenge_type)
+            .field(
+                "registry",
+                &self.registry.as_ref().map(|_| "dyn ChallengeRegistry"),
+            )
+            .finish()
+    }
+}
+
+impl Default for CaptchaManager {
+    /// Secret is read from `CAPTCHA_SECRET`, falling back to a random
+    /// per-process secret. No registry, 300 seconds challenge TTL.
+    fn default() -> Self {
+        let secret = match env::var(SECRET_ENV) {
+            Ok(secret) if !secret.is_empty() => secret.into_bytes(),
+            _ => {
+                warn!(
+                    "{SECRET_ENV} is not set, using a random secret: challenges will not verify across restarts or instances"
+                );
+
+                let mut secret = vec![0u8; 32];
+                rng().fill_bytes(&mut secret);
+                secret
+            }
+        };
+
+        let noise = NoiseOptions::default();
+        let gen_opts = challenge::GenerationOptions::default();
+
+        Self {
+            registry: None,
+            challenge_ttl: CaptchaConfig::default().ttl_secs,
+            secret: Arc::new(Zeroizing::new(secret)),
+            challenge_type: ChallengeType::default(),
+            rotations: Arc::default(),
+            sample_hashes: hash_samples(&gen_opts, &noise),
+            noise,
+            gen_opts,
+            buffers: Arc::default(),
+            clock: Arc::new(SystemClock),
+            images: Arc::default(),
+        }
+    }
 }
 
 impl CaptchaManager {
@@ -35,80 +122,393 @@ impl CaptchaManager {
         noise: NoiseOptions,
         registry: Option<Arc<dyn ChallengeRegistry>>,
         gen_opts: challenge::GenerationOptions,
-    ) -> Self {
-        Self {
+    ) -> Result<Self> {
+        gen_opts.validate()?;
+        noise.validate()?;
+
+        Ok(Self {
             registry,
             challenge_ttl,
+            secret: Arc::new(Zeroizing::new(secret.into_bytes())),
+            challenge_type: ChallengeType::default(),
+            rotations: Arc::default(),
+            sample_hashes: hash_samples(&gen_opts, &noise),
             noise,
-            secret: Zeroizing::new(secret.into_bytes()),
             gen_opts,
+            buffers: Arc::default(),
+            clock: Arc::new(SystemClock),
+            images: Arc::default(),
+        })
+    }
+
+    /// Replace the system clock used to stamp and expire challenges.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Seconds a challenge stays valid after it is issued.
+    pub fn challenge_ttl(&self) -> u64 {
+        self.challenge_ttl
+    }
+
+    pub fn generation_opts(&self) -> &challenge::GenerationOptions {
+        &self.gen_opts
+    }
+
+    pub fn noise_opts(&self) -> &NoiseOptions {
+        &self.noise
+    }
+
+    /// Generate challenges of `challenge_type` instead of [`ChallengeType::Rotation`].
+    pub fn with_challenge_type(mut self, challenge_type: ChallengeType) -> Result<Self> {
+        challenge_type.validate(&self.gen_opts)?;
+        self.challenge_type = challenge_type;
+
+        Ok(self)
+    }
+
+    /// Download base images from `urls` and add them to the pool next to
+    /// the bundled samples. Fails on the first unreachable or non JPEG/PNG/WebP image.
+    #[cfg(feature = "reqwest")]
+    pub async fn with_remote_images(self, urls: Vec<String>) -> Result<Self> {
+        let client = reqwest::Client::new();
+
+        for url in urls {
+            let bytes = client
+                .get(&url)
+                .send()
+                .await
+                .and_then(|resp| resp.error_for_status())
+                .map_err(|e| CaptchaError::Internal(format!("download {url}: {e}")))?
+                .bytes()
+                .await
+                .map_err(|e| CaptchaError::Internal(format!("download {url}: {e}")))?;
+
+            self.add_image(url, &bytes)?;
+        }
+
+        Ok(self)
+    }
+
+    /// Add or replace the base image loaded from `source`.
+    #[cfg(any(test, feature = "reqwest"))]
+    fn add_image(&self, source: String, bytes: &[u8]) -> Result<()> {
+        let hash = self.image_hash(bytes);
+        if let Some(old) = add_base_image(&self.images, source, bytes, hash)? {
+            self.rotations.evict(old);
+        }
+
+        Ok(())
+    }
+
+    /// Load every JPEG, PNG or WebP file (by extension, contents are checked
+    /// on load) in `path` into the base image pool and keep it in
+    /// sync as files are added, changed or removed. Watching stops when
+    /// the returned watcher is dropped.
+    #[cfg(feature = "notify")]
+    pub fn watch_image_dir(&self, path: &Path) -> Result<notify::RecommendedWatcher> {
+        use notify::{EventKind, RecursiveMode, Watcher};
+
+        for entry in fs::read_dir(path).map_err(CaptchaError::Io)? {
+            let file = entry.map_err(CaptchaError::Io)?.path();
+            if is_base_image_path(&file) {
+                self.sync_image_file(&file);
+            }
+        }
+
+        let mgr = self.clone();
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+                Ok(event) if !matches!(event.kind, EventKind::Access(_)) => {
+                    for file in event.paths.iter().filter(|p| is_base_image_path(p)) {
+                        mgr.sync_image_file(file);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => warn!("image dir watch error: {e}"),
+            })
+            .map_err(|e| CaptchaError::Internal(format!("create image dir watcher: {e}")))?;
+
+        watcher
+            .watch(path, RecursiveMode::NonRecursive)
+            .map_err(|e| CaptchaError::Internal(format!("watch {}: {e}", path.display())))?;
+        info!(path = %path.display(), "watching base image directory");
+
+        Ok(watcher)
+    }
+
+    /// Add `file` to the pool if it exists, drop it otherwise.
+    #[cfg(feature = "notify")]
+    fn sync_image_file(&self, file: &Path) {
+        let source = file.display().to_string();
+        if !file.is_file() {
+            if let Some(old) = remove_base_image(&self.images, &source) {
+                self.rotations.evict(old);
+            }
+            return;
+        }
+
+        let added = fs::read(file).map_err(CaptchaError::Io).and_then(|bytes| {
+            let hash = self.image_hash(&bytes);
+            add_base_image(&self.images, source.clone(), &bytes, hash)
+        });
+        match added {
+            Ok(Some(old)) => self.rotations.evict(old),
+            Ok(None) => {}
+            Err(e) => warn!(source = %source, "skipping base image: {e}"),
+        }
+    }
+
+    #[cfg(any(test, feature = "reqwest", feature = "notify"))]
+    fn image_hash(&self, bytes: &[u8]) -> u64 {
+        RotatedImageCache::image_hash(bytes, self.gen_opts.cell_size, self.noise.background)
+    }
+
+    /// Build a manager from `CAPTCHA_*` environment variables.
+    ///
+    /// `CAPTCHA_SECRET` is required. `CAPTCHA_TTL_SECS`, `CAPTCHA_CELL_SIZE`
+    /// and `CAPTCHA_JPEG_QUALITY` fall back to defaults, an in-memory registry
+    /// is attached only when `CAPTCHA_MAX_ATTEMPTS` is set.
+    pub fn from_env() -> Result<Self> {
+        Self::from_config(env_config(&|name| env::var(name))?)
+    }
+
+    /// Read a `CaptchaConfig` from a `.toml` or `.json` file,
+    /// depending on which of the `toml`/`serde_json` features is enabled.
+    #[cfg(any(feature = "toml", feature = "serde_json"))]
+    pub fn from_config_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).map_err(CaptchaError::Io)?;
+        let parse_error =
+            |e: &dyn Display| CaptchaError::InvalidInput(format!("{}: {e}", path.display()));
+
+        let config: CaptchaConfig = match path.extension().and_then(|ext| ext.to_str()) {
+            #[cfg(feature = "toml")]
+            Some("toml") => toml::from_str(&contents).map_err(|e| parse_error(&e))?,
+            #[cfg(feature = "serde_json")]
+            Some("json") => serde_json::from_str(&contents).map_err(|e| parse_error(&e))?,
+            _ => {
+                return Err(CaptchaError::InvalidInput(format!(
+                    "unsupported config file: {}",
+                    path.display()
+                )));
+            }
+        };
+
+        Self::from_config(config)
+    }
+
+    pub fn from_config(config: CaptchaConfig) -> Result<Self> {
+        if config.secret.is_empty() {
+            return Err(CaptchaError::InvalidInput("secret must be set".into()));
         }
+
+        let registry = match config.max_attempts {
+            Some(0) => {
+                return Err(CaptchaError::InvalidInput(
+                    "max_attempts must be greater than 0".into(),
+                ));
+            }
+            Some(max_attempts) => Some(Arc::new(ChallengeInMemoryRegistry::new(
+                config.ttl_secs,
+                max_attempts,
+            )) as Arc<dyn ChallengeRegistry>),
+            None => None,
+        };
+
+        let gen_opts = config.generation_opts()?;
+        let noise = config.noise();
+
+        Self::new(config.secret, config.ttl_secs, noise, registry, gen_opts)?
+            .with_challenge_type(config.challenge_type)
     }
 
+    #[must_use = "generated challenge should be sent to the client"]
     pub fn generate_challenge<T: SpriteTarget>(&self) -> Result<challenge::CaptchaChallenge<T>> {
-        let sample_image = match SAMPLE_IMAGES.choose(&mut rng()) {
-            Some(img) => *img,
-            None => return Err(CaptchaError::Internal("no sample images available".into())),
+        let (idx, extra, hash) = {
+            let images = self
+                .images
+                .read()
+                .map_err(|_| CaptchaError::Internal("base image pool lock poisoned".into()))?;
+            let total = SAMPLE_IMAGES.len() + images.len();
+            if total == 0 {
+                return Err(CaptchaError::Internal("no sample images available".into()));
+            }
+
+            let idx = rng().random_range(0..total);
+            match idx.checked_sub(SAMPLE_IMAGES.len()) {
+                Some(i) => (idx, Some(Arc::clone(&images[i].1)), images[i].2),
+                None => (idx, None, self.sample_hashes[idx]),
+            }
+        };
+        // Bundled images come first, runtime-added ones follow
+        debug!(image_index = idx, "generating challenge from sample image");
+        let sample_image = match &extra {
+            Some(img) => img,
+            None => SAMPLE_IMAGES[idx],
         };
 
-        let challenge = challenge::generate::<T>(
-            sample_image,
-            self.secret.as_slice(),
-            &self.gen_opts,
-            self.noise,
-        )?;
+        let challenge = ChallengeBuilder::new(self.challenge_type)
+            .options(self.gen_opts.clone())
+            .noise(self.noise)
+            .rotation_cache(&self.rotations)
+            .image_hash(hash)
+            .buffer_pool(&self.buffers)
+            .clock(self.clock.as_ref())
+            .ttl(self.challenge_ttl)
+            .build::<T>(sample_image, self.secret.as_slice())?;
 
         if let Some(reg) = &self.registry {
             reg.register(&challenge.challenge_id);
         }
 
-        let (format, quality, lossless) = match self.gen_opts.sprite_format {
-            SpriteFormat::Jpeg { quality } => ("jpeg", quality, false),
-            SpriteFormat::Webp { quality, lossless } => (
-                if lossless { "webp-lossless" } else { "webp" },
-                quality,
-                lossless,
-            ),
+        let (format, quality) = match self.gen_opts.sprite_format {
+            SpriteFormat::Jpeg { quality, .. } => ("jpeg", quality),
+            SpriteFormat::Webp { quality, .. } => ("webp", quality),
+            SpriteFormat::WebpLossless { compression_level } => {
+                ("webp-lossless", compression_level)
+            }
         };
 
         info!(
             cell_size = self.gen_opts.cell_size,
             format = format,
             quality = quality,
-            lossless = lossless,
             "captcha generated"
         );
 
         Ok(challenge)
     }
 
+    /// Generate `count` challenges, on the rayon pool with the `parallel`
+    /// feature. Every successful challenge is registered; failures are
+    /// returned in place and don't abort the rest of the batch.
+    pub fn generate_batch<T: SpriteTarget + Send>(
+        &self,
+        count: usize,
+    ) -> Vec<Result<challenge::CaptchaChallenge<T>>> {
+        #[cfg(feature = "parallel")]
+        {
+            (0..count)
+                .into_par_iter()
+                .map(|_| self.generate_challenge())
+                .collect()
+        }
+        #[cfg(not(feature = "parallel"))]
+        {
+            (0..count).map(|_| self.generate_challenge()).collect()
+        }
+    }
+
+    /// Same as [`CaptchaManager::generate_batch`], but runs on `pool`
+    /// instead of the global rayon pool.
+    #[cfg(feature = "parallel")]
+    pub fn generate_batch_in<T: SpriteTarget + Send>(
+        &self,
+        count: usize,
+        pool: &rayon::ThreadPool,
+    ) -> Vec<Result<challenge::CaptchaChallenge<T>>> {
+        pool.install(|| {
+            (0..count)
+                .into_par_iter()
+                .map(|_| self.generate_challenge())
+                .collect()
+        })
+    }
+
+    #[must_use = "verification outcome must be checked"]
     pub fn verify_challenge(&self, challenge_id: &str, selected_index: u8) -> Result<bool> {
+        self.verify_selection(challenge_id, &[selected_index])
+    }
+
+    /// Verify a set of selected tiles, which must match the correct ones
+    /// exactly. Use for [`ChallengeType::SelectAll`] challenges.
+    #[must_use = "verification outcome must be checked"]
+    pub fn verify_selection(&self, challenge_id: &str, selected: &[u8]) -> Result<bool> {
         if challenge_id.is_empty() {
             return Err(CaptchaError::InvalidInput(
                 "Challenge ID cannot be empty".into(),
             ));
         }
 
-        if selected_index == 0 || selected_index > 9 {
+        if selected.is_empty() {
+            return Err(CaptchaError::InvalidInput("No tiles selected".into()));
+        }
+
+        let tiles = self.gen_opts.tile_count();
+        if selected
+            .iter()
+            .any(|&idx| idx == 0 || u32::from(idx) > tiles)
+        {
             return Err(CaptchaError::InvalidInput(
                 "Selected index out of bounds".into(),
             ));
         }
 
+        self.check_registered(challenge_id, || {
+            challenge::verify_at(
+                self.secret.as_slice(),
+                challenge_id,
+                selected,
+                self.challenge_ttl,
+                self.clock.now_secs(),
+            )
+        })
+    }
+
+    /// Verify an [`ChallengeType::AngleDial`] answer: `submitted_angle_deg`
+    /// is how far the user turned the tile clockwise, accepted within
+    /// `tolerance_deg` of upright. The tolerance must be in
+    /// `(0, MAX_ANGLE_TOLERANCE_DEG]`.
+    #[must_use = "verification outcome must be checked"]
+    pub fn verify_angle(
+        &self,
+        challenge_id: &str,
+        submitted_angle_deg: f32,
+        tolerance_deg: f32,
+    ) -> Result<bool> {
+        if challenge_id.is_empty() {
+            return Err(CaptchaError::InvalidInput(
+                "Challenge ID cannot be empty".into(),
+            ));
+        }
+
+        if !submitted_angle_deg.is_finite() {
+            return Err(CaptchaError::InvalidInput(
+                "Submitted angle must be finite".into(),
+            ));
+        }
+
+        if !(tolerance_deg > 0.0 && tolerance_deg <= MAX_ANGLE_TOLERANCE_DEG) {
+            return Err(CaptchaError::InvalidInput(format!(
+                "Angle tolerance must be in (0, {MAX_ANGLE_TOLERANCE_DEG}]"
+            )));
+        }
+
+        self.check_registered(challenge_id, || {
+            challenge::verify_angle_at(
+                self.secret.as_slice(),
+                challenge_id,
+                submitted_angle_deg,
+                tolerance_deg,
+                self.challenge_ttl,
+                self.clock.now_secs(),
+            )
+        })
+    }
+
+    /// Runs `verify` unless the registry rejects `challenge_id`, then
+    /// records the outcome.
+    fn check_registered(&self, challenge_id: &str, verify: impl FnOnce() -> bool) -> Result<bool> {
         if let Some(registry) = &self.registry {
             let result = registry.check(challenge_id);
-            if result != RegistryCheckResult::Ok {
+            if !matches!(result, RegistryCheckResult::Ok { .. }) {
                 warn!("challenge rejected by registry: {result}");
                 return Err(CaptchaError::Registry(result));
             }
         }
 
-        let valid = challenge::verify(
-            self.secret.as_slice(),
-            challenge_id,
-            selected_index,
-            self.challenge_ttl,
-        );
+        let valid = verify();
 
         if valid {
             if let Some(registry) = &self.registry {
@@ -124,3 +524,606 @@ impl CaptchaManager {
         Ok(valid)
     }
 }
+
+/// Add or replace the image for `source`, returning the hash of the one it
+/// replaced.
+#[cfg(any(test, feature = "reqwest", feature = "notify"))]
+fn add_base_image(
+    images: &BaseImages,
+    source: String,
+    bytes: &[u8],
+    hash: u64,
+) -> Result<Option<u64>> {
+    validate_image_format(bytes).map_err(|err| match err {
+        CaptchaError::InvalidInput(msg) => CaptchaError::InvalidInput(format!("{source}: {msg}")),
+        other => other,
+    })?;
+
+    let mut images = images
+        .write()
+        .map_err(|_| CaptchaError::Internal("base image pool lock poisoned".into()))?;
+    let replaced = images
+        .iter()
+        .position(|(s, ..)| *s == source)
+        .map(|i| images.remove(i).2);
+    info!(source = %source, "base image added");
+    images.push((source, Arc::from(bytes), hash));
+
+    Ok(replaced)
+}
+
+#[cfg(feature = "notify")]
+fn remove_base_image(images: &BaseImages, source: &str) -> Option<u64> {
+    let mut images = images.write().ok()?;
+    let idx = images.iter().position(|(s, ..)| s == source)?;
+    info!(source = %source, "base image removed");
+
+    Some(images.remove(idx).2)
+}
+
+/// Rotation cache hashes of `SAMPLE_IMAGES` as rendered with `gen_opts`.
+fn hash_samples(gen_opts: &challenge::GenerationOptions, noise: &NoiseOptions) -> Arc<[u64]> {
+    SAMPLE_IMAGES
+        .iter()
+        .map(|buf| RotatedImageCache::image_hash(buf, gen_opts.cell_size, noise.background))
+        .collect()
+}
+
+/// Extensions of the formats `validate_image_format` accepts. Removed files
+/// can't be sniffed, so the watcher goes by name.
+#[cfg(feature = "notify")]
+fn is_base_image_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            ["jpg", "jpeg", "png", "webp"]
+                .iter()
+                .any(|known| ext.eq_ignore_ascii_case(known))
+        })
+}
+
+/// Looks up an environment variable, `env::var` outside of tests.
+type VarLookup<'a> = &'a dyn Fn(&str) -> std::result::Result<String, VarError>;
+
+/// Reads the `from_env` config through `lookup`.
+fn env_config(lookup: VarLookup<'_>) -> Result<CaptchaConfig> {
+    let defaults = CaptchaConfig::default();
+    let secret = env_var::<String>(lookup, SECRET_ENV)?
+        .filter(|secret| !secret.is_empty())
+        .ok_or_else(|| CaptchaError::InvalidInput(format!("{SECRET_ENV}: not set")))?;
+
+    Ok(CaptchaConfig {
+        secret,
+        ttl_secs: env_var(lookup, "CAPTCHA_TTL_SECS")?.unwrap_or(defaults.ttl_secs),
+        cell_size: env_var(lookup, "CAPTCHA_CELL_SIZE")?.unwrap_or(defaults.cell_size),
+        quality: env_var(lookup, "CAPTCHA_JPEG_QUALITY")?.unwrap_or(defaults.quality),
+        max_attempts: env_var(lookup, "CAPTCHA_MAX_ATTEMPTS")?,
+        ..defaults
+    })
+}
+
+fn env_var<T>(lookup: VarLookup<'_>, name: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match lookup(name) {
+        Ok(raw) => raw
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|e| CaptchaError::InvalidInput(format!("{name}: {e}"))),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(e) => Err(CaptchaError::InvalidInput(format!("{name}: {e}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_var_parse_error_names_variable() {
+        let lookup: VarLookup<'_> = &|name| match name {
+            "CAPTCHA_TTL_SECS" => Ok("not-a-number".into()),
+            "CAPTCHA_CELL_SIZE" => Ok(" 96 ".into()),
+            _ => Err(VarError::NotPresent),
+        };
+
+        match env_var::<u64>(lookup, "CAPTCHA_TTL_SECS") {
+            Err(CaptchaError::InvalidInput(msg)) => assert!(msg.starts_with("CAPTCHA_TTL_SECS")),
+            _ => panic!("expected InvalidInput error"),
+        }
+        assert!(matches!(
+            env_var::<u32>(lookup, "CAPTCHA_CELL_SIZE"),
+            Ok(Some(96))
+        ));
+        assert!(matches!(
+            env_var::<u64>(lookup, "CAPTCHA_MAX_ATTEMPTS"),
+            Ok(None)
+        ));
+    }
+
+    #[test]
+    fn test_env_config_requires_secret() {
+        for secret in [Err(VarError::NotPresent), Ok(String::new())] {
+            let lookup: VarLookup<'_> = &|name| match name {
+                SECRET_ENV => secret.clone(),
+                _ => Err(VarError::NotPresent),
+            };
+            match env_config(lookup) {
+                Err(CaptchaError::InvalidInput(msg)) => assert_eq!(msg, "CAPTCHA_SECRET: not set"),
+                other => panic!("expected InvalidInput error, got {other:?}"),
+            }
+        }
+
+        let lookup: VarLookup<'_> = &|name| match name {
+            SECRET_ENV => Ok("s3cr3t".into()),
+            "CAPTCHA_MAX_ATTEMPTS" => Ok("5".into()),
+            _ => Err(VarError::NotPresent),
+        };
+        let config = env_config(lookup).expect("valid env");
+        assert_eq!(config.secret, "s3cr3t");
+        assert_eq!(config.max_attempts, Some(5));
+        assert_eq!(config.ttl_secs, CaptchaConfig::default().ttl_secs);
+    }
+
+    #[test]
+    fn test_cloned_manager_shares_registry() {
+        let registry = Arc::new(ChallengeInMemoryRegistry::new(60, 3));
+        let mgr = CaptchaManager::new(
+            "secret".into(),
+            60,
+            NoiseOptions::default(),
+            Some(registry),
+            challenge::GenerationOptions::default(),
+        )
+        .expect("valid options");
+        let cloned = mgr.clone();
+
+        let ch = mgr
+            .generate_challenge::<crate::SpriteBinary>()
+            .expect("Failed to generate challenge");
+
+        assert!(
+            cloned
+                .verify_challenge(&ch.challenge_id, ch.correct_number)
+                .unwrap()
+        );
+        assert!(matches!(
+            mgr.verify_challenge(&ch.challenge_id, ch.correct_number),
+            Err(CaptchaError::Registry(RegistryCheckResult::AlreadyVerified))
+        ));
+    }
+
+    #[test]
+    fn test_option_getters() {
+        let mgr = CaptchaManager::default();
+        assert_eq!(
+            mgr.generation_opts(),
+            &challenge::GenerationOptions::default()
+        );
+        assert_eq!(mgr.noise_opts(), &NoiseOptions::default());
+        assert_eq!(mgr.challenge_ttl(), CaptchaConfig::default().ttl_secs);
+    }
+
+    #[test]
+    fn test_generate_batch_registers_every_challenge() {
+        let registry = Arc::new(ChallengeInMemoryRegistry::new(60, 3));
+        let mgr = CaptchaManager::new(
+            "secret".into(),
+            60,
+            NoiseOptions::default(),
+            Some(registry),
+            challenge::GenerationOptions {
+                cell_size: 64,
+                ..Default::default()
+            },
+        )
+        .expect("valid options");
+
+        let batch = mgr.generate_batch::<crate::SpriteBinary>(4);
+        assert_eq!(batch.len(), 4);
+
+        for ch in batch {
+            let ch = ch.expect("Failed to generate challenge");
+            assert!(
+                mgr.verify_challenge(&ch.challenge_id, ch.correct_number)
+                    .unwrap()
+            );
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_generate_batch_in_custom_pool() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let mgr = CaptchaManager::default();
+
+        let batch = mgr.generate_batch_in::<crate::SpriteBinary>(3, &pool);
+        assert_eq!(batch.len(), 3);
+        assert!(batch.iter().all(|ch| ch.is_ok()));
+    }
+
+    #[test]
+    fn test_generate_populates_rotation_cache_and_buffer_pool() {
+        let mgr = CaptchaManager::new(
+            "secret".into(),
+            60,
+            NoiseOptions::default(),
+            None,
+            challenge::GenerationOptions {
+                cell_size: 64,
+                ..Default::default()
+            },
+        )
+        .expect("valid options");
+        assert!(mgr.rotations.is_empty());
+
+        let _ = mgr
+            .generate_challenge::<crate::SpriteBinary>()
+            .expect("Failed to generate challenge");
+        assert_eq!(mgr.rotations.len(), 12);
+        assert_eq!(mgr.buffers.len(), 1);
+
+        let cloned = mgr.clone();
+        assert!(Arc::ptr_eq(&mgr.rotations, &cloned.rotations));
+    }
+
+    #[test]
+    fn test_new_rejects_small_cell_size() {
+        let result = CaptchaManager::new(
+            "secret".into(),
+            60,
+            NoiseOptions::default(),
+            None,
+            challenge::GenerationOptions {
+                cell_size: 0,
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            result.unwrap_err(),
+            CaptchaError::InvalidInput("cell_size must be at least 32".into())
+        );
+    }
+
+    #[test]
+    fn test_custom_clock_drives_expiry() {
+        let clock = Arc::new(crate::testing::MockClock::new(1_000));
+        let mgr = CaptchaManager::new(
+            "secret".into(),
+            60,
+            NoiseOptions::default(),
+            None,
+            challenge::GenerationOptions::default(),
+        )
+        .expect("valid options")
+        .with_clock(clock.clone());
+
+        let ch = mgr
+            .generate_challenge::<crate::SpriteBinary>()
+            .expect("Failed to generate challenge");
+        assert_eq!(ch.timestamp, 1_000);
+
+        clock.set(1_060);
+        assert!(
+            mgr.verify_challenge(&ch.challenge_id, ch.correct_number)
+                .unwrap()
+        );
+
+        clock.advance(1);
+        assert!(
+            !mgr.verify_challenge(&ch.challenge_id, ch.correct_number)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_extend_ttl_outlives_registry_ttl_not_manager_ttl() {
+        let clock = Arc::new(crate::testing::MockClock::new(1_000));
+        let registry = Arc::new(ChallengeInMemoryRegistry::new(10, 3).with_clock(clock.clone()));
+        let mgr = CaptchaManager::new(
+            "secret".into(),
+            30,
+            NoiseOptions::default(),
+            Some(registry.clone()),
+            challenge::GenerationOptions {
+                cell_size: 64,
+                ..Default::default()
+            },
+        )
+        .expect("valid options")
+        .with_clock(clock.clone());
+
+        let [idle, extended, late] = std::array::from_fn(|_| {
+            mgr.generate_challenge::<crate::SpriteBinary>()
+                .expect("Failed to generate challenge")
+        });
+
+        clock.advance(8);
+        registry.extend_ttl(&extended.challenge_id, 10);
+        registry.extend_ttl(&late.challenge_id, 60);
+        clock.advance(8);
+
+        assert!(matches!(
+            mgr.verify_challenge(&idle.challenge_id, idle.correct_number),
+            Err(CaptchaError::Registry(RegistryCheckResult::NotRegistered))
+        ));
+        assert!(
+            mgr.verify_challenge(&extended.challenge_id, extended.correct_number)
+                .unwrap()
+        );
+
+        // Still live in the registry, but the signed timestamp is too old
+        clock.advance(15);
+        assert!(
+            !mgr.verify_challenge(&late.challenge_id, late.correct_number)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_add_image_validates_format() {
+        let mgr = CaptchaManager::new(
+            "secret".into(),
+            60,
+            NoiseOptions::default(),
+            None,
+            challenge::GenerationOptions::default(),
+        )
+        .expect("valid options");
+
+        assert!(matches!(
+            mgr.add_image("logo.gif".into(), b"GIF89a"),
+            Err(CaptchaError::InvalidInput(_))
+        ));
+
+        mgr.add_image("a.jpg".into(), SAMPLE_IMAGES[0]).unwrap();
+        mgr.add_image("a.jpg".into(), SAMPLE_IMAGES[1]).unwrap();
+        assert_eq!(mgr.images.read().unwrap().len(), 1);
+        assert_eq!(&*mgr.images.read().unwrap()[0].1, SAMPLE_IMAGES[1]);
+    }
+
+    #[cfg(feature = "notify")]
+    #[test]
+    fn test_watch_image_dir_tracks_added_and_removed_files() {
+        let dir = std::env::temp_dir().join(format!("geronimo-watch-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("base.jpg");
+        fs::write(&file, SAMPLE_IMAGES[0]).unwrap();
+        let mut png = Vec::new();
+        image::load_from_memory(SAMPLE_IMAGES[1])
+            .unwrap()
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        fs::write(dir.join("base.PNG"), png).unwrap();
+        fs::write(dir.join("notes.txt"), b"ignored").unwrap();
+
+        let mgr = CaptchaManager::new(
+            "secret".into(),
+            60,
+            NoiseOptions::default(),
+            None,
+            challenge::GenerationOptions::default(),
+        )
+        .expect("valid options");
+        let _watcher = mgr.watch_image_dir(&dir).unwrap();
+        assert_eq!(mgr.images.read().unwrap().len(), 2);
+        let hash = mgr.image_hash(SAMPLE_IMAGES[0]);
+        let base = image::load_from_memory(SAMPLE_IMAGES[0]).unwrap();
+        mgr.rotations
+            .get_or_rotate(hash, &base, &[90.0], mgr.noise.background);
+
+        fs::remove_file(&file).unwrap();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while mgr.images.read().unwrap().len() > 1 && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        let images = mgr.images.read().unwrap();
+        assert_eq!(images.len(), 1);
+        assert!(images[0].0.ends_with("base.PNG"));
+        drop(images);
+        assert!(mgr.rotations.get_all(hash, &[90.0]).is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_select_all_manager_roundtrip() {
+        let mgr = CaptchaManager::new(
+            "secret".into(),
+            60,
+            NoiseOptions::default(),
+            None,
+            challenge::GenerationOptions {
+                cell_size: 64,
+                ..Default::default()
+            },
+        )
+        .expect("valid options")
+        .with_challenge_type(ChallengeType::SelectAll { correct_count: 2 })
+        .expect("valid challenge type");
+
+        let ch = mgr.generate_challenge::<crate::SpriteBinary>().unwrap();
+        assert_eq!(ch.correct_numbers.len(), 2);
+        assert!(
+            !mgr.verify_challenge(&ch.challenge_id, ch.correct_number)
+                .unwrap()
+        );
+        assert!(
+            mgr.verify_selection(&ch.challenge_id, &ch.correct_numbers)
+                .unwrap()
+        );
+        assert_eq!(
+            mgr.verify_selection(&ch.challenge_id, &[]).unwrap_err(),
+            CaptchaError::InvalidInput("No tiles selected".into())
+        );
+    }
+
+    #[test]
+    fn test_angle_dial_manager_roundtrip() {
+        let mgr = CaptchaManager::new(
+            "secret".into(),
+            60,
+            NoiseOptions::default(),
+            None,
+            challenge::GenerationOptions {
+                cell_size: 64,
+                ..Default::default()
+            },
+        )
+        .expect("valid options")
+        .with_challenge_type(ChallengeType::AngleDial)
+        .expect("valid challenge type");
+
+        let ch = mgr.generate_challenge::<crate::SpriteBinary>().unwrap();
+        assert!(ch.correct_numbers.is_empty());
+        assert_eq!((ch.cols, ch.rows), (1, 1));
+        assert_eq!((ch.sprite_dbg.width(), ch.sprite_dbg.height()), (64, 64));
+
+        let undo = 360.0 - ch.dial_angle as f32;
+        let id = &ch.challenge_id;
+        assert!(mgr.verify_angle(id, undo + 4.5, 5.0).unwrap());
+        assert!(mgr.verify_angle(id, undo - 720.0, 5.0).unwrap());
+        assert!(!mgr.verify_angle(id, undo + 20.0, 5.0).unwrap());
+        assert!(!mgr.verify_selection(id, &[1]).unwrap());
+        assert!(matches!(
+            mgr.verify_angle(id, f32::NAN, 5.0),
+            Err(CaptchaError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            mgr.verify_angle(id, undo, 0.0),
+            Err(CaptchaError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            mgr.verify_angle(id, undo, 90.0),
+            Err(CaptchaError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_debug_redacts_secret() {
+        let mgr = CaptchaManager::new(
+            "super-secret-hmac-key".into(),
+            60,
+            NoiseOptions::default(),
+            None,
+            challenge::GenerationOptions::default(),
+        )
+        .expect("valid options");
+        let dbg = format!("{mgr:?}");
+
+        assert!(dbg.contains("secret: \"[REDACTED]\""));
+        assert!(!dbg.contains("super-secret-hmac-key"));
+    }
+
+    #[test]
+    fn test_from_config_validates_quality_range() {
+        let config = CaptchaConfig {
+            secret: "secret".into(),
+            format: crate::SpriteEncoding::WebpLossless,
+            quality: 10,
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            CaptchaManager::from_config(config),
+            Err(CaptchaError::InvalidInput(_))
+        ));
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_from_config_file_toml() {
+        let path = std::env::temp_dir().join(format!("geronimo-{}.toml", uuid::Uuid::new_v4()));
+        fs::write(
+            &path,
+            "secret = \"s3cr3t\"\nttl_secs = 120\nformat = \"webp\"\nquality = 80\nmax_attempts = 3\n",
+        )
+        .unwrap();
+
+        let mgr = CaptchaManager::from_config_file(&path).expect("valid toml config");
+        let _ = fs::remove_file(path);
+
+        assert_eq!(mgr.challenge_ttl, 120);
+        assert!(mgr.registry.is_some());
+        assert!(matches!(
+            mgr.gen_opts.sprite_format,
+            SpriteFormat::Webp {
+                quality: 80,
+                method: 4,
+            }
+        ));
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_from_config_file_toml_tunables() {
+        let path = std::env::temp_dir().join(format!("geronimo-{}.toml", uuid::Uuid::new_v4()));
+        fs::write(
+            &path,
+            r#"
+secret = "s3cr3t"
+cols = 4
+rows = 3
+instruction_text = "Pick every upright tile"
+draw_grid_lines = true
+grayscale_tile_probability = 0.25
+perspective_warp = true
+challenge_type = { select_all = { correct_count = 2 } }
+color_jitter = { brightness = 0.1 }
+
+[post_noise]
+count = 40
+shape = { gaussian = { std_dev = 8.0 } }
+"#,
+        )
+        .unwrap();
+
+        let mgr = CaptchaManager::from_config_file(&path).expect("valid toml config");
+        let _ = fs::remove_file(path);
+
+        let opts = &mgr.gen_opts;
+        assert_eq!((opts.cols, opts.rows), (4, 3));
+        assert_eq!(
+            opts.instruction_text.as_deref(),
+            Some("Pick every upright tile")
+        );
+        assert!(opts.draw_grid_lines && opts.perspective_warp);
+        assert_eq!(opts.grayscale_tile_probability, 0.25);
+        assert_eq!(
+            opts.color_jitter,
+            Some(crate::ColorJitter {
+                brightness: 0.1,
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            mgr.challenge_type,
+            ChallengeType::SelectAll { correct_count: 2 }
+        );
+        assert_eq!(mgr.noise.pre_noise, NoiseOptions::default().pre_noise);
+        assert_eq!(mgr.noise.post_noise.count, 40);
+        assert_eq!(
+            mgr.noise.post_noise.shape,
+            crate::NoisePattern::Gaussian { std_dev: 8.0 }
+        );
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_from_config_file_json_requires_secret() {
+        let path = std::env::temp_dir().join(format!("geronimo-{}.json", uuid::Uuid::new_v4()));
+        fs::write(&path, r#"{ "cell_size": 120 }"#).unwrap();
+
+        let result = CaptchaManager::from_config_file(&path);
+        let _ = fs::remove_file(path);
+
+        assert!(matches!(result, Err(CaptchaError::InvalidInput(_))));
+    }
+}
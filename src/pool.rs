@@ -0,0 +1,77 @@
+use crossbeam_queue::ArrayQueue;
+use image::{Rgba, RgbaImage};
+
+const DEFAULT_CAPACITY: usize = 16;
+
+/// Bounded pool of sprite buffers reused across challenges.
+pub struct SpriteBufferPool {
+    buffers: ArrayQueue<RgbaImage>,
+}
+
+impl SpriteBufferPool {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            buffers: ArrayQueue::new(capacity.max(1)),
+        }
+    }
+
+    /// Takes a pooled buffer of the given size filled with `fill`, or
+    /// allocates one when none is available.
+    pub fn acquire(&self, width: u32, height: u32, fill: Rgba<u8>) -> RgbaImage {
+        match self.buffers.pop() {
+            Some(mut buf) if buf.dimensions() == (width, height) => {
+                buf.pixels_mut().for_each(|px| *px = fill);
+                buf
+            }
+            _ => RgbaImage::from_pixel(width, height, fill),
+        }
+    }
+
+    /// Returns a buffer to the pool, dropping it when the pool is full.
+    pub fn release(&self, buf: RgbaImage) {
+        let _ = self.buffers.push(buf);
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+}
+
+impl Default for SpriteBufferPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acquire_reuses_and_refills_buffer() {
+        let pool = SpriteBufferPool::new(1);
+        let white = Rgba([255, 255, 255, 255]);
+        let black = Rgba([0, 0, 0, 255]);
+
+        let mut buf = pool.acquire(4, 4, white);
+        buf.put_pixel(0, 0, black);
+        let ptr = buf.as_raw().as_ptr();
+        pool.release(buf);
+        pool.release(RgbaImage::new(4, 4));
+        assert_eq!(pool.len(), 1);
+
+        let buf = pool.acquire(4, 4, white);
+        assert_eq!(buf.as_raw().as_ptr(), ptr);
+        assert!(buf.pixels().all(|px| *px == white));
+        assert!(pool.is_empty());
+
+        pool.release(buf);
+        let resized = pool.acquire(8, 2, black);
+        assert_eq!(resized.dimensions(), (8, 2));
+        assert!(resized.pixels().all(|px| *px == black));
+    }
+}
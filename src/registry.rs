@@ -1,73 +1,215 @@
-use crate::utils::get_timestamp;
+#[cfg(feature = "render")]
+use crate::utils::{Clock, SystemClock};
 
+use alloc::sync::Arc;
+use core::fmt;
+#[cfg(feature = "render")]
 use dashmap::DashMap;
-use std::fmt;
+#[cfg(feature = "render")]
 use std::sync::Mutex;
 
 /// This file defines trait for the challenge registry implementation that
 /// stores generated challenges in memory or database, checks how
 /// many resolving attempts performed to prevent brute-force.
+///
+/// The trait is object safe and shared as `Arc<dyn ChallengeRegistry>`,
+/// cloning the `Arc` shares one registry. `Arc<T>` implements the trait
+/// too, so a shared handle can be passed wherever a registry is expected.
 pub trait ChallengeRegistry: Send + Sync {
     fn register(&self, id: &str);
     fn check(&self, id: &str) -> RegistryCheckResult;
     fn verify(&self, id: &str);
     fn note_attempt(&self, id: &str, success: bool);
+
+    /// Register with a ttl other than the registry-wide one. Registries
+    /// without per-challenge expiry ignore `ttl` and fall back to `register`.
+    fn register_with_ttl(&self, id: &str, ttl: u64) {
+        let _ = ttl;
+        self.register(id);
+    }
+
+    /// Push the expiry of a registered challenge back by `extra_secs`. No-op
+    /// by default.
+    ///
+    /// This only moves the registry's own entry. `CaptchaManager` still
+    /// rejects ids whose signed timestamp is older than its `challenge_ttl`,
+    /// so extending only helps while the registry ttl is the shorter one.
+    fn extend_ttl(&self, id: &str, extra_secs: u64) {
+        let _ = (id, extra_secs);
+    }
+}
+
+impl<T: ChallengeRegistry + ?Sized> ChallengeRegistry for Arc<T> {
+    fn register(&self, id: &str) {
+        (**self).register(id)
+    }
+
+    fn check(&self, id: &str) -> RegistryCheckResult {
+        (**self).check(id)
+    }
+
+    fn verify(&self, id: &str) {
+        (**self).verify(id)
+    }
+
+    fn note_attempt(&self, id: &str, success: bool) {
+        (**self).note_attempt(id, success)
+    }
+
+    fn register_with_ttl(&self, id: &str, ttl: u64) {
+        (**self).register_with_ttl(id, ttl)
+    }
+
+    fn extend_ttl(&self, id: &str, extra_secs: u64) {
+        (**self).extend_ttl(id, extra_secs)
+    }
 }
 
-#[derive(PartialEq, Debug)]
+#[must_use]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum RegistryCheckResult {
-    Ok,
+    Ok {
+        remaining_attempts: u16,
+    },
     AlreadyVerified,
     NotRegistered,
-    MaxAttemptsLimitExceeded,
+    /// `remaining` is always 0, kept for symmetry with `Ok`.
+    MaxAttemptsLimitExceeded {
+        remaining: u16,
+    },
 }
 
 impl fmt::Display for RegistryCheckResult {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", format!("{self:?}").to_uppercase())
+        let name = match self {
+            RegistryCheckResult::Ok { .. } => "OK",
+            RegistryCheckResult::AlreadyVerified => "ALREADYVERIFIED",
+            RegistryCheckResult::NotRegistered => "NOTREGISTERED",
+            RegistryCheckResult::MaxAttemptsLimitExceeded { .. } => "MAXATTEMPTSLIMITEXCEEDED",
+        };
+
+        f.write_str(name)
     }
 }
 
+#[cfg(all(feature = "render", any(debug_assertions, feature = "debug-utils")))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChallengeSnapshot {
+    pub id: String,
+    pub verified: bool,
+    pub attempts: u16,
+    pub age_secs: u64,
+}
+
+#[cfg(feature = "render")]
 struct ChallengeStatus {
     verified: bool,
     attempts_count: u16,
     timestamp: u64,
+    ttl: u64,
+}
+
+#[cfg(feature = "render")]
+impl ChallengeStatus {
+    /// A ttl of 0 expires challenges as soon as they're registered.
+    fn is_live(&self, now: u64) -> bool {
+        self.ttl > 0 && now.saturating_sub(self.timestamp) <= self.ttl
+    }
 }
 
+#[cfg(feature = "render")]
 struct Wheel {
     buckets: Vec<Vec<String>>, // ids scheduled to expire at bucket index
     pos: usize,                // current bucket index (advances with time)
     last_tick: u64,            // last observed time (secs)
-    len: usize,                // buckets length == ttl (secs)
+    len: usize,                // buckets length == ttl + 1 (secs)
+}
+
+#[cfg(feature = "render")]
+impl Wheel {
+    fn new(ttl: u64, now: u64) -> Self {
+        // One full turn is ttl + 1 seconds, the first age `check` rejects.
+        let len = ttl.saturating_add(1) as usize;
+        Wheel {
+            buckets: vec![Vec::new(); len],
+            pos: (now % len as u64) as usize,
+            last_tick: now,
+            len,
+        }
+    }
 }
 
+#[cfg(feature = "ahash")]
+type IdHasher = ahash::RandomState;
+#[cfg(all(feature = "render", not(feature = "ahash")))]
+type IdHasher = std::hash::RandomState;
+
+#[cfg(feature = "render")]
 pub struct ChallengeInMemoryRegistry {
-    cache: DashMap<String, ChallengeStatus>,
+    cache: DashMap<String, ChallengeStatus, IdHasher>,
     max_attempts: u16,
     ttl: u64,
     wheel: Mutex<Wheel>,
+    clock: Arc<dyn Clock>,
 }
 
+#[cfg(feature = "render")]
 impl ChallengeInMemoryRegistry {
     pub fn new(ttl: u64, max_attempts: u16) -> Self {
-        let now = get_timestamp();
-        let len = ttl.max(1) as usize;
-        let pos = (now as usize) % len;
-        let wheel = Wheel {
-            buckets: vec![Vec::new(); len],
-            pos,
-            last_tick: now,
-            len,
-        };
-
         Self {
-            cache: DashMap::new(),
+            cache: DashMap::default(),
             max_attempts,
             ttl,
-            wheel: Mutex::new(wheel),
+            wheel: Mutex::new(Wheel::new(ttl, SystemClock.now_secs())),
+            clock: Arc::new(SystemClock),
         }
     }
 
+    /// Replace the system clock used to age challenges. Should be the
+    /// same clock the manager stamps challenges with.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        let wheel = Wheel::new(self.ttl, clock.now_secs());
+        *self.wheel.get_mut().unwrap_or_else(|e| e.into_inner()) = wheel;
+        self.clock = clock;
+        self
+    }
+
+    /// Evict every expired challenge right away instead of waiting for the
+    /// wheel to reach its bucket. Returns how many entries were removed.
+    pub fn drain_expired(&self) -> usize {
+        let now = self.clock.now_secs();
+        let before = self.cache.len();
+        self.cache.retain(|_, cs| cs.is_live(now));
+        before.saturating_sub(self.cache.len())
+    }
+
+    /// Number of buckets in the expiry wheel, `ttl + 1`.
+    pub fn wheel_bucket_count(&self) -> usize {
+        self.wheel.lock().unwrap_or_else(|e| e.into_inner()).len
+    }
+
+    /// Bucket the expiry wheel last advanced to.
+    pub fn wheel_current_position(&self) -> usize {
+        self.wheel.lock().unwrap_or_else(|e| e.into_inner()).pos
+    }
+
+    /// Dump all active (not yet expired) challenges for debugging.
+    #[cfg(any(debug_assertions, feature = "debug-utils"))]
+    pub fn snapshot(&self) -> Vec<ChallengeSnapshot> {
+        let now = self.clock.now_secs();
+
+        self.cache
+            .iter()
+            .filter(|entry| entry.is_live(now))
+            .map(|entry| ChallengeSnapshot {
+                id: entry.key().clone(),
+                verified: entry.verified,
+                attempts: entry.attempts_count,
+                age_secs: now.saturating_sub(entry.timestamp),
+            })
+            .collect()
+    }
+
     fn advance_wheel(&self, now: u64) {
         let mut w = self.wheel.lock().unwrap();
         if now <= w.last_tick {
@@ -83,10 +225,13 @@ impl ChallengeInMemoryRegistry {
 
             for id in expired_ids {
                 if let Some(cs_ref) = self.cache.get(&id) {
-                    let expired = now.saturating_sub(cs_ref.timestamp) >= self.ttl;
+                    let expired = !cs_ref.is_live(now);
                     drop(cs_ref);
                     if expired {
                         let _ = self.cache.remove(&id);
+                    } else {
+                        // Outlives one turn (longer per-challenge ttl), look again next turn
+                        w.buckets[pos].push(id);
                     }
                 } else {
                     // already removed
@@ -97,18 +242,26 @@ impl ChallengeInMemoryRegistry {
         w.last_tick = now;
     }
 
-    fn schedule_expiry(&self, id: &str, now: u64) {
+    fn schedule_expiry(&self, id: &str, now: u64, ttl: u64) {
         let mut w = self.wheel.lock().unwrap();
 
-        // Schedule at (now + ttl) bucket
-        let target = (now + self.ttl) as usize % w.len;
+        // Schedule at (now + ttl + 1) bucket, at most one full turn from now.
+        // Taken modulo len so timestamps near u64::MAX can't overflow.
+        let len = w.len as u64;
+        let delay = ttl.min(self.ttl).saturating_add(1) % len;
+        let target = ((now % len + delay) % len) as usize;
         w.buckets[target].push(id.to_string());
     }
 }
 
+#[cfg(feature = "render")]
 impl ChallengeRegistry for ChallengeInMemoryRegistry {
     fn register(&self, id: &str) {
-        let now = get_timestamp();
+        self.register_with_ttl(id, self.ttl);
+    }
+
+    fn register_with_ttl(&self, id: &str, ttl: u64) {
+        let now = self.clock.now_secs();
         self.advance_wheel(now);
         self.cache.insert(
             id.to_string(),
@@ -116,13 +269,37 @@ impl ChallengeRegistry for ChallengeInMemoryRegistry {
                 verified: false,
                 attempts_count: 0,
                 timestamp: now,
+                ttl,
             },
         );
-        self.schedule_expiry(id, now);
+        self.schedule_expiry(id, now, ttl);
+    }
+
+    fn extend_ttl(&self, id: &str, extra_secs: u64) {
+        let now = self.clock.now_secs();
+        self.advance_wheel(now);
+
+        let ttl = {
+            let Some(mut challenge_ref) = self.cache.get_mut(id) else {
+                return;
+            };
+            let cs = challenge_ref.value_mut();
+            if !cs.is_live(now) {
+                return;
+            }
+
+            // Restart the clock on what was left plus the extra seconds
+            let remaining = cs.ttl - now.saturating_sub(cs.timestamp);
+            cs.timestamp = now;
+            cs.ttl = remaining.saturating_add(extra_secs);
+            cs.ttl
+        };
+        // A copy left in the old bucket is re-queued or dropped like any other
+        self.schedule_expiry(id, now, ttl);
     }
 
     fn check(&self, id: &str) -> RegistryCheckResult {
-        let now = get_timestamp();
+        let now = self.clock.now_secs();
         self.advance_wheel(now);
 
         if let Some(challenge_ref) = self.cache.get(id) {
@@ -132,11 +309,13 @@ impl ChallengeRegistry for ChallengeInMemoryRegistry {
             }
 
             if cs.attempts_count >= self.max_attempts {
-                return RegistryCheckResult::MaxAttemptsLimitExceeded;
+                return RegistryCheckResult::MaxAttemptsLimitExceeded { remaining: 0 };
             }
 
-            if now.saturating_sub(cs.timestamp) <= self.ttl {
-                return RegistryCheckResult::Ok;
+            if cs.is_live(now) {
+                return RegistryCheckResult::Ok {
+                    remaining_attempts: self.max_attempts - cs.attempts_count,
+                };
             }
         }
 
@@ -153,16 +332,19 @@ impl ChallengeRegistry for ChallengeInMemoryRegistry {
     fn note_attempt(&self, id: &str, success: bool) {
         if let Some(mut challenge_ref) = self.cache.get_mut(id) {
             let cs = challenge_ref.value_mut();
-            if !success {
+            if success {
+                cs.attempts_count = 0;
+            } else {
                 cs.attempts_count = cs.attempts_count.saturating_add(1);
             }
         }
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "render"))]
 mod tests {
     use super::*;
+    use crate::testing::MockClock;
     use std::sync::Arc;
 
     const DEFAULT_TTL: u64 = 60;
@@ -173,7 +355,31 @@ mod tests {
         let challenge_id = "challenge-123";
 
         registry.register(challenge_id);
-        assert_eq!(registry.check(challenge_id), RegistryCheckResult::Ok);
+        assert_eq!(
+            registry.check(challenge_id),
+            RegistryCheckResult::Ok {
+                remaining_attempts: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_cloned_dyn_registry_shares_state() {
+        let registry: Arc<dyn ChallengeRegistry> =
+            Arc::new(ChallengeInMemoryRegistry::new(DEFAULT_TTL, 3));
+        let handle = registry.clone();
+        handle.register("challenge-1");
+
+        // Arc<dyn ChallengeRegistry> is itself a registry
+        fn check_via<R: ChallengeRegistry>(r: &R) -> RegistryCheckResult {
+            r.check("challenge-1")
+        }
+        assert_eq!(
+            check_via(&registry),
+            RegistryCheckResult::Ok {
+                remaining_attempts: 3
+            }
+        );
     }
 
     #[test]
@@ -186,6 +392,26 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_check_results_group_in_hash_map() {
+        let registry = ChallengeInMemoryRegistry::new(DEFAULT_TTL, 1);
+        registry.register("a");
+        registry.register("b");
+
+        let mut outcomes = std::collections::HashMap::new();
+        for id in ["a", "b", "c"] {
+            *outcomes.entry(registry.check(id)).or_insert(0) += 1;
+        }
+
+        assert_eq!(
+            outcomes[&RegistryCheckResult::Ok {
+                remaining_attempts: 1
+            }],
+            2
+        );
+        assert_eq!(outcomes[&RegistryCheckResult::NotRegistered], 1);
+    }
+
     #[test]
     fn test_check_already_verified() {
         let registry = ChallengeInMemoryRegistry::new(DEFAULT_TTL, 1);
@@ -204,18 +430,254 @@ mod tests {
         let challenge_id = "challenge-123";
         registry.register(challenge_id);
 
-        assert_eq!(registry.check(challenge_id), RegistryCheckResult::Ok);
+        assert_eq!(
+            registry.check(challenge_id),
+            RegistryCheckResult::Ok {
+                remaining_attempts: 2
+            }
+        );
 
         registry.note_attempt(challenge_id, false);
-        assert_eq!(registry.check(challenge_id), RegistryCheckResult::Ok);
+        assert_eq!(
+            registry.check(challenge_id),
+            RegistryCheckResult::Ok {
+                remaining_attempts: 1
+            }
+        );
 
         registry.note_attempt(challenge_id, false);
         assert_eq!(
             registry.check(challenge_id),
-            RegistryCheckResult::MaxAttemptsLimitExceeded
+            RegistryCheckResult::MaxAttemptsLimitExceeded { remaining: 0 }
         );
     }
 
+    #[test]
+    fn test_successful_attempt_resets_counter() {
+        let registry = ChallengeInMemoryRegistry::new(DEFAULT_TTL, 2);
+        let challenge_id = "challenge-123";
+        registry.register(challenge_id);
+
+        registry.note_attempt(challenge_id, false);
+        registry.note_attempt(challenge_id, false);
+        registry.note_attempt(challenge_id, true);
+        assert_eq!(
+            registry.check(challenge_id),
+            RegistryCheckResult::Ok {
+                remaining_attempts: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_challenge_expires_after_ttl() {
+        let clock = Arc::new(MockClock::new(1_000));
+        let registry = ChallengeInMemoryRegistry::new(DEFAULT_TTL, 1).with_clock(clock.clone());
+        registry.register("challenge-1");
+
+        clock.advance(DEFAULT_TTL);
+        assert!(matches!(
+            registry.check("challenge-1"),
+            RegistryCheckResult::Ok { .. }
+        ));
+
+        clock.advance(1);
+        assert_eq!(
+            registry.check("challenge-1"),
+            RegistryCheckResult::NotRegistered
+        );
+        assert!(registry.cache.is_empty());
+    }
+
+    #[test]
+    fn test_expiry_is_measured_from_registration() {
+        let clock = Arc::new(MockClock::new(1_000));
+        let registry = ChallengeInMemoryRegistry::new(DEFAULT_TTL, 1).with_clock(clock.clone());
+        registry.register("early");
+
+        clock.advance(30);
+        registry.register("late");
+
+        clock.advance(DEFAULT_TTL - 29);
+        assert_eq!(registry.check("early"), RegistryCheckResult::NotRegistered);
+        assert!(matches!(
+            registry.check("late"),
+            RegistryCheckResult::Ok { .. }
+        ));
+    }
+
+    #[test]
+    fn test_wheel_handles_timestamps_near_u64_max() {
+        let clock = Arc::new(MockClock::new(u64::MAX - 1 - DEFAULT_TTL));
+        let registry = ChallengeInMemoryRegistry::new(DEFAULT_TTL, 1).with_clock(clock.clone());
+        registry.register("challenge-1");
+
+        clock.set(u64::MAX - 1);
+        assert!(matches!(
+            registry.check("challenge-1"),
+            RegistryCheckResult::Ok { .. }
+        ));
+        assert_eq!(registry.wheel.lock().unwrap().last_tick, u64::MAX - 1);
+
+        clock.set(u64::MAX);
+        assert_eq!(
+            registry.check("challenge-1"),
+            RegistryCheckResult::NotRegistered
+        );
+        assert!(registry.cache.is_empty());
+    }
+
+    #[test]
+    fn test_drain_expired_evicts_without_wheel() {
+        let clock = Arc::new(MockClock::new(1_000));
+        let registry = ChallengeInMemoryRegistry::new(DEFAULT_TTL, 1).with_clock(clock.clone());
+        registry.register("old-1");
+        registry.register("old-2");
+
+        clock.advance(DEFAULT_TTL);
+        registry.register("fresh");
+
+        assert_eq!(registry.drain_expired(), 0);
+
+        clock.advance(1);
+        assert_eq!(registry.drain_expired(), 2);
+        assert_eq!(registry.cache.len(), 1);
+        assert!(registry.cache.contains_key("fresh"));
+    }
+
+    #[test]
+    fn test_wheel_diagnostics() {
+        let clock = Arc::new(MockClock::new(1_000));
+        let registry = ChallengeInMemoryRegistry::new(DEFAULT_TTL, 1).with_clock(clock.clone());
+        assert_eq!(registry.wheel_bucket_count(), DEFAULT_TTL as usize + 1);
+
+        let start = registry.wheel_current_position();
+        clock.advance(5);
+        let _ = registry.check("missing");
+        assert_eq!(
+            registry.wheel_current_position(),
+            (start + 5) % registry.wheel_bucket_count()
+        );
+    }
+
+    #[test]
+    fn test_register_with_shorter_ttl() {
+        let clock = Arc::new(MockClock::new(1_000));
+        let registry = ChallengeInMemoryRegistry::new(DEFAULT_TTL, 1).with_clock(clock.clone());
+        registry.register_with_ttl("short", 10);
+        registry.register("default");
+
+        clock.advance(11);
+        assert_eq!(registry.check("short"), RegistryCheckResult::NotRegistered);
+        assert!(!registry.cache.contains_key("short"));
+        assert!(matches!(
+            registry.check("default"),
+            RegistryCheckResult::Ok { .. }
+        ));
+    }
+
+    #[test]
+    fn test_register_with_longer_ttl() {
+        let clock = Arc::new(MockClock::new(1_000));
+        let registry = ChallengeInMemoryRegistry::new(DEFAULT_TTL, 1).with_clock(clock.clone());
+        registry.register_with_ttl("long", DEFAULT_TTL * 3);
+
+        for _ in 0..DEFAULT_TTL * 3 {
+            clock.advance(1);
+            assert!(matches!(
+                registry.check("long"),
+                RegistryCheckResult::Ok { .. }
+            ));
+        }
+
+        clock.advance(1);
+        assert_eq!(registry.check("long"), RegistryCheckResult::NotRegistered);
+
+        // The wheel revisits the entry once per turn and drops it on the next one
+        clock.set(1_000 + 3 * (DEFAULT_TTL + 1));
+        let _ = registry.check("long");
+        assert!(registry.cache.is_empty());
+    }
+
+    #[test]
+    fn test_extend_ttl_delays_expiry() {
+        let clock = Arc::new(MockClock::new(1_000));
+        let registry = ChallengeInMemoryRegistry::new(DEFAULT_TTL, 1).with_clock(clock.clone());
+        registry.register("challenge-1");
+
+        clock.advance(DEFAULT_TTL - 1);
+        registry.extend_ttl("challenge-1", 30);
+
+        clock.advance(30);
+        assert!(matches!(
+            registry.check("challenge-1"),
+            RegistryCheckResult::Ok { .. }
+        ));
+
+        clock.advance(2);
+        assert_eq!(
+            registry.check("challenge-1"),
+            RegistryCheckResult::NotRegistered
+        );
+
+        // Rescheduled, so the wheel drops it within a turn
+        clock.advance(DEFAULT_TTL + 1);
+        let _ = registry.check("challenge-1");
+        assert!(registry.cache.is_empty());
+    }
+
+    #[test]
+    fn test_extend_ttl_ignores_expired_challenge() {
+        let clock = Arc::new(MockClock::new(1_000));
+        let registry = ChallengeInMemoryRegistry::new(DEFAULT_TTL, 1).with_clock(clock.clone());
+        registry.register("challenge-1");
+
+        clock.advance(DEFAULT_TTL + 1);
+        registry.extend_ttl("challenge-1", 30);
+        assert_eq!(
+            registry.check("challenge-1"),
+            RegistryCheckResult::NotRegistered
+        );
+    }
+
+    #[test]
+    fn test_zero_ttl_expires_immediately() {
+        let clock = Arc::new(MockClock::new(1_000));
+        let registry = ChallengeInMemoryRegistry::new(0, 3).with_clock(clock.clone());
+        registry.register("challenge-1");
+
+        assert_eq!(
+            registry.check("challenge-1"),
+            RegistryCheckResult::NotRegistered
+        );
+
+        clock.advance(1);
+        assert_eq!(
+            registry.check("challenge-1"),
+            RegistryCheckResult::NotRegistered
+        );
+        assert!(registry.cache.is_empty());
+    }
+
+    #[cfg(any(debug_assertions, feature = "debug-utils"))]
+    #[test]
+    fn test_snapshot_lists_active_challenges() {
+        let registry = ChallengeInMemoryRegistry::new(DEFAULT_TTL, 3);
+        registry.register("challenge-1");
+        registry.register("challenge-2");
+        registry.note_attempt("challenge-2", false);
+        registry.verify("challenge-1");
+
+        let mut snapshot = registry.snapshot();
+        snapshot.sort_by(|a, b| a.id.cmp(&b.id));
+
+        assert_eq!(snapshot.len(), 2);
+        assert!(snapshot[0].verified);
+        assert_eq!(snapshot[0].attempts, 0);
+        assert!(!snapshot[1].verified);
+        assert_eq!(snapshot[1].attempts, 1);
+    }
+
     #[test]
     fn test_concurrent_usage_safe() {
         use std::thread;
@@ -237,7 +699,49 @@ mod tests {
 
         for i in 0..10 {
             let id = format!("challenge-{i}");
-            assert_eq!(registry.check(&id), RegistryCheckResult::Ok);
+            assert!(matches!(
+                registry.check(&id),
+                RegistryCheckResult::Ok { .. }
+            ));
         }
     }
+
+    #[test]
+    fn test_concurrent_register_and_check_same_id() {
+        use std::thread;
+
+        const MAX_ATTEMPTS: u16 = 5;
+        let registry = Arc::new(ChallengeInMemoryRegistry::new(DEFAULT_TTL, MAX_ATTEMPTS));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let reg = registry.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        reg.register("same-id");
+                        match reg.check("same-id") {
+                            RegistryCheckResult::Ok { remaining_attempts } => {
+                                assert!((1..=MAX_ATTEMPTS).contains(&remaining_attempts))
+                            }
+                            RegistryCheckResult::MaxAttemptsLimitExceeded { remaining: 0 } => {}
+                            other => panic!("unexpected check result: {other:?}"),
+                        }
+                        reg.note_attempt("same-id", false);
+                    }
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert_eq!(registry.cache.len(), 1);
+        registry.register("same-id");
+        assert_eq!(
+            registry.check("same-id"),
+            RegistryCheckResult::Ok {
+                remaining_attempts: MAX_ATTEMPTS
+            }
+        );
+    }
 }
@@ -1,22 +1,52 @@
-use crate::image::rotate_image;
-use crate::{CaptchaError, GenerationOptions};
+use crate::cache::{RotatedImageCache, rotate_all};
+use crate::image::{
+    ColorJitter, NoiseOptions, apply_noise, flatten_alpha, perspective_warp, rotate_image, sharpen,
+    validate_image_format,
+};
+use crate::pool::SpriteBufferPool;
+use crate::{CaptchaError, ChallengeType, GenerationOptions};
 
 use ab_glyph::{FontArc, PxScale};
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64_STANDARD};
-use image::{DynamicImage, GenericImage, ImageBuffer, ImageReader, Limits, Rgba, imageops};
-use imageproc::drawing::draw_text_mut;
+use image::{
+    DynamicImage, GenericImage, ImageBuffer, ImageReader, Limits, Rgba, RgbaImage, imageops,
+};
+use imageproc::drawing::{draw_hollow_rect_mut, draw_line_segment_mut, draw_text_mut, text_size};
+use imageproc::rect::Rect;
 use once_cell::sync::Lazy;
 use rand::prelude::SliceRandom;
-use rand::{Rng, rng};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng, rng};
 #[cfg(feature = "parallel")]
 use rayon::prelude::*;
 use std::io::Cursor;
+#[cfg(feature = "tokio")]
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+#[cfg(feature = "tokio")]
+use uuid::Uuid;
 
-static FONT: Lazy<FontArc> = Lazy::new(|| {
+/// Wrong angles tiles are drawn at, a grid uses `tile_count - 1` at most.
+pub(crate) const INCORRECT_ANGLES: [f32; 11] = [
+    38.0, 88.0, 114.0, 138.0, 176.0, 200.0, 229.0, 255.0, 278.0, 314.0, 320.0,
+];
+/// Gap in pixels between neighbouring cells.
+const TILE_SPACING: u32 = 4;
+const GRID_LINE_COLOR: Rgba<u8> = Rgba([48, 48, 52, 255]);
+const MIN_BANNER_HEIGHT: u32 = 24;
+const HIGHLIGHT_WIDTH: u32 = 3;
+/// Largest inward shift of a warped tile corner, as a fraction of the tile size.
+const MAX_WARP: f32 = 0.12;
+
+pub(crate) static FONT: Lazy<FontArc> = Lazy::new(|| {
     FontArc::try_from_slice(include_bytes!("../assets/Roboto-Bold.ttf"))
         .expect("embedded font should be valid")
 });
 
+/// Below this many tiles rendering them on the rayon pool costs more than it saves.
+#[cfg(feature = "parallel")]
+const PARALLEL_MIN_TILES: usize = 6;
+
 pub trait SpriteTarget: Sized {
     fn from_bytes(bytes: Vec<u8>, mime: &'static str) -> Self;
 }
@@ -29,6 +59,7 @@ impl SpriteTarget for SpriteUri {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct SpriteBinary {
     pub bytes: Vec<u8>,
     pub mime: &'static str,
@@ -40,111 +71,581 @@ impl SpriteTarget for SpriteBinary {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+/// Shared encoded bytes for caches that hand out clones, without the mime type.
+impl SpriteTarget for Arc<Vec<u8>> {
+    fn from_bytes(bytes: Vec<u8>, _mime: &'static str) -> Self {
+        Arc::new(bytes)
+    }
+}
+
+/// Raw encoded bytes without the mime type, `Vec<u8>` is taken over without copying.
+#[cfg(feature = "bytes")]
+impl SpriteTarget for bytes::Bytes {
+    fn from_bytes(bytes: Vec<u8>, _mime: &'static str) -> Self {
+        bytes::Bytes::from(bytes)
+    }
+}
+
+/// Sprite headed for disk, written with [`SpriteFile::write_to`] through a
+/// `tokio::fs::File` so a server can serve it from a file without blocking.
+/// The sprite is encoded in memory first like any other target, so this
+/// does not lower peak memory; `write_to` consumes it and frees the buffer
+/// once the file is written.
+#[cfg(feature = "tokio")]
+pub struct SpriteFile {
+    bytes: Vec<u8>,
+    pub mime: &'static str,
+}
+
+#[cfg(feature = "tokio")]
+impl SpriteFile {
+    /// Writes the sprite into `dir` under a fresh `geronimo-captcha-<uuid>`
+    /// name and returns its path. The file is never removed by the crate,
+    /// delete it once it has been served or point `dir` at a directory that
+    /// is cleaned up regularly.
+    pub async fn write_to(self, dir: &Path) -> std::io::Result<PathBuf> {
+        use tokio::io::AsyncWriteExt;
+
+        let ext = match self.mime {
+            "image/jpeg" => "jpg",
+            mime => mime.strip_prefix("image/").unwrap_or("bin"),
+        };
+        let path = dir.join(format!("geronimo-captcha-{}.{ext}", Uuid::new_v4()));
+
+        let mut file = tokio::fs::File::create(&path).await?;
+        file.write_all(&self.bytes).await?;
+        file.flush().await?;
+
+        Ok(path)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl SpriteTarget for SpriteFile {
+    fn from_bytes(bytes: Vec<u8>, mime: &'static str) -> Self {
+        SpriteFile { bytes, mime }
+    }
+}
+
+/// JPEG chroma subsampling. 4:2:0 halves colour resolution both ways for
+/// noticeably smaller files; 4:4:4 keeps full colour detail (sharper label
+/// and noise edges) at roughly 30–50% more bytes for the same quality.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum JpegSubsampling {
+    #[default]
+    #[cfg_attr(feature = "serde", serde(alias = "4:2:0"))]
+    Yuv420,
+    #[cfg_attr(feature = "serde", serde(alias = "4:4:4"))]
+    Yuv444,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum SpriteFormat {
-    Jpeg { quality: u8 },
-    Webp { quality: u8, lossless: bool },
+    /// Baseline or progressive JPEG, `quality` is 1–100.
+    Jpeg {
+        quality: u8,
+        progressive: bool,
+        chroma_subsampling: JpegSubsampling,
+    },
+    /// Lossy WebP, `quality` is 0–100. `method` trades speed for size,
+    /// 0 is the fastest and 6 the smallest output (clamped to 6); libwebp
+    /// defaults to 4.
+    Webp { quality: u8, method: u8 },
+    /// Lossless WebP, `compression_level` is 0–9 where 0 is
+    /// the fastest and 9 the smallest output (clamped to 9).
+    WebpLossless { compression_level: u8 },
+}
+
+impl SpriteFormat {
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            SpriteFormat::Jpeg { .. } => "image/jpeg",
+            SpriteFormat::Webp { .. } | SpriteFormat::WebpLossless { .. } => "image/webp",
+        }
+    }
+}
+
+/// Formats as the MIME type, e.g. `image/jpeg`.
+impl std::fmt::Display for SpriteFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.mime_type())
+    }
 }
 
 impl Default for SpriteFormat {
     fn default() -> Self {
-        SpriteFormat::Jpeg { quality: 70 }
+        SpriteFormat::Jpeg {
+            quality: 70,
+            progressive: false,
+            chroma_subsampling: JpegSubsampling::Yuv420,
+        }
+    }
+}
+
+/// Accepts the Rust form, `{"Jpeg": {"quality": 70}}`, and the flat one,
+/// `{"type": "jpeg", "quality": 70}`. Omitted settings use encoder defaults.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SpriteFormat {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::Deserialize;
+
+        #[derive(Deserialize)]
+        struct Jpeg {
+            quality: u8,
+            #[serde(default)]
+            progressive: bool,
+            #[serde(default)]
+            chroma_subsampling: JpegSubsampling,
+        }
+
+        #[derive(Deserialize)]
+        struct Webp {
+            quality: u8,
+            #[serde(default = "default_webp_method")]
+            method: u8,
+        }
+
+        #[derive(Deserialize)]
+        struct WebpLossless {
+            compression_level: u8,
+        }
+
+        fn default_webp_method() -> u8 {
+            4
+        }
+
+        #[derive(Deserialize)]
+        #[serde(tag = "type", rename_all = "kebab-case")]
+        enum Flat {
+            #[serde(alias = "jpg")]
+            Jpeg(Jpeg),
+            Webp(Webp),
+            #[serde(alias = "webp_lossless")]
+            WebpLossless(WebpLossless),
+        }
+
+        #[derive(Deserialize)]
+        enum Tagged {
+            Jpeg(Jpeg),
+            Webp(Webp),
+            WebpLossless(WebpLossless),
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Flat(Flat),
+            Tagged(Tagged),
+        }
+
+        let repr = Repr::deserialize(deserializer).map_err(|_| {
+            serde::de::Error::custom(
+                "expected a sprite format like {\"type\": \"jpeg\", \"quality\": 70}",
+            )
+        })?;
+
+        Ok(match repr {
+            Repr::Flat(Flat::Jpeg(f)) | Repr::Tagged(Tagged::Jpeg(f)) => SpriteFormat::Jpeg {
+                quality: f.quality,
+                progressive: f.progressive,
+                chroma_subsampling: f.chroma_subsampling,
+            },
+            Repr::Flat(Flat::Webp(f)) | Repr::Tagged(Tagged::Webp(f)) => SpriteFormat::Webp {
+                quality: f.quality,
+                method: f.method,
+            },
+            Repr::Flat(Flat::WebpLossless(f)) | Repr::Tagged(Tagged::WebpLossless(f)) => {
+                SpriteFormat::WebpLossless {
+                    compression_level: f.compression_level,
+                }
+            }
+        })
     }
 }
 
+/// Same as [`create_sprite`], but memory-maps the base image at `path`
+/// instead of reading it onto the heap first.
+#[cfg(feature = "mmap")]
+pub fn create_sprite_from_path(
+    path: &std::path::Path,
+    opts: &GenerationOptions,
+    noise: &NoiseOptions,
+) -> crate::Result<(DynamicImage, u8)> {
+    let file = std::fs::File::open(path).map_err(CaptchaError::Io)?;
+    // SAFETY: the mapping is read-only and dropped before returning; the
+    // caller must not truncate the file while the sprite is being built.
+    let mmap = unsafe { memmap2::Mmap::map(&file) }.map_err(CaptchaError::Io)?;
+
+    create_sprite(&mmap, opts, noise, None, None)
+}
+
+/// Renders a [`ChallengeType::Rotation`] sprite.
+#[cfg(any(test, feature = "test-utils", feature = "mmap"))]
+#[doc(hidden)]
 pub fn create_sprite(
     base_buf: &[u8],
     opts: &GenerationOptions,
+    noise: &NoiseOptions,
+    cache: Option<&RotatedImageCache>,
+    buffers: Option<&SpriteBufferPool>,
 ) -> crate::Result<(DynamicImage, u8)> {
-    let mut reader = ImageReader::with_format(Cursor::new(base_buf), image::ImageFormat::Jpeg);
-    if let Some(limits) = opts.limits.clone() {
-        reader.limits(limits);
-    } else {
-        let mut limits = Limits::default();
-        limits.max_image_width = Some(4096);
-        limits.max_image_height = Some(4096);
-        limits.max_alloc = Some(128 * 1024 * 1024);
+    let (sprite, correct) = render_sprite(
+        ChallengeType::Rotation,
+        Base::Encoded(base_buf, None),
+        opts,
+        noise,
+        cache,
+        buffers,
+        &mut rng(),
+    )?;
 
-        reader.limits(limits);
+    Ok((sprite, correct[0]))
+}
+
+/// Same as [`create_sprite`] for a base image that is already decoded,
+/// skipping the decode step when the same image is reused.
+pub fn create_sprite_from_image(
+    base: &DynamicImage,
+    opts: &GenerationOptions,
+    noise: &NoiseOptions,
+) -> crate::Result<(DynamicImage, u8)> {
+    let (sprite, correct) = render_sprite(
+        ChallengeType::Rotation,
+        Base::Decoded(base),
+        opts,
+        noise,
+        None,
+        None,
+        &mut rng(),
+    )?;
+
+    Ok((sprite, correct[0]))
+}
+
+/// What a rendered sprite asks for.
+pub(crate) enum SpriteAnswer {
+    /// 1-based numbers of every correct tile, ascending.
+    Tiles(Vec<u8>),
+    /// Clockwise angle in degrees the dial tile is drawn at.
+    Angle(u16),
+}
+
+pub(crate) fn create_challenge_sprite(
+    challenge_type: ChallengeType,
+    base_buf: &[u8],
+    opts: &GenerationOptions,
+    noise: &NoiseOptions,
+    cache: Option<&RotatedImageCache>,
+    image_hash: Option<u64>,
+    buffers: Option<&SpriteBufferPool>,
+) -> crate::Result<(DynamicImage, SpriteAnswer)> {
+    if challenge_type == ChallengeType::AngleDial {
+        let (sprite, angle) = render_dial(base_buf, opts, noise, &mut rng())?;
+        return Ok((sprite, SpriteAnswer::Angle(angle)));
     }
 
-    let base = reader.decode().map_err(CaptchaError::Decode)?.resize_exact(
-        opts.cell_size,
-        opts.cell_size,
-        imageops::FilterType::Nearest,
-    );
-    let mut rng = rng();
+    let (sprite, correct) = render_sprite(
+        challenge_type,
+        Base::Encoded(base_buf, image_hash),
+        opts,
+        noise,
+        cache,
+        buffers,
+        &mut rng(),
+    )?;
 
-    let correct_angle = 0.0;
-    let incorrect_angles = [
-        38.0, 88.0, 114.0, 138.0, 176.0, 200.0, 229.0, 255.0, 278.0, 314.0, 320.0,
-    ];
+    Ok((sprite, SpriteAnswer::Tiles(correct)))
+}
 
-    let mut angles = Vec::with_capacity(1 + incorrect_angles.len());
-    angles.push(correct_angle);
-    angles.extend_from_slice(&incorrect_angles);
+/// Same as [`create_sprite`] with every random choice drawn from `seed`,
+/// so the same inputs produce the same sprite and answer.
+#[cfg(any(test, feature = "test-utils"))]
+#[doc(hidden)]
+pub fn create_sprite_seeded(
+    base_buf: &[u8],
+    opts: &GenerationOptions,
+    noise: &NoiseOptions,
+    seed: u64,
+) -> crate::Result<(DynamicImage, u8)> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let (sprite, correct) = render_sprite(
+        ChallengeType::Rotation,
+        Base::Encoded(base_buf, None),
+        opts,
+        noise,
+        None,
+        None,
+        &mut rng,
+    )?;
 
-    let precomputed: Vec<(f32, image::RgbaImage)> = {
-        #[cfg(feature = "parallel")]
-        {
-            angles
-                .par_iter()
-                .map(|&a| (a, rotate_image(&base, a).to_rgba8()))
-                .collect()
+    Ok((sprite, correct[0]))
+}
+
+/// Base image a grid is rendered from. Only encoded bytes can be looked up
+/// in the rotation cache, since they are what it is keyed by. Their
+/// [`RotatedImageCache::image_hash`] is computed here unless known.
+#[derive(Clone, Copy)]
+enum Base<'a> {
+    Encoded(&'a [u8], Option<u64>),
+    Decoded(&'a DynamicImage),
+}
+
+fn render_sprite<R: Rng>(
+    challenge_type: ChallengeType,
+    base: Base<'_>,
+    opts: &GenerationOptions,
+    noise: &NoiseOptions,
+    cache: Option<&RotatedImageCache>,
+    buffers: Option<&SpriteBufferPool>,
+    rng: &mut R,
+) -> crate::Result<(DynamicImage, Vec<u8>)> {
+    opts.validate()?;
+    challenge_type.validate(opts)?;
+
+    let layout = match challenge_type {
+        ChallengeType::Rotation => upright_layout(1, opts.tile_count(), rng),
+        ChallengeType::SelectAll { correct_count } => {
+            upright_layout(correct_count, opts.tile_count(), rng)
         }
-        #[cfg(not(feature = "parallel"))]
-        {
-            angles
-                .iter()
-                .map(|&a| (a, rotate_image(&base, a).to_rgba8()))
-                .collect()
+        ChallengeType::AngleDial => {
+            return Err(CaptchaError::InvalidInput(
+                "AngleDial challenges have no tile grid".into(),
+            ));
         }
     };
 
-    let mut tiles = vec![(true, correct_angle)];
-    let mut others = incorrect_angles.to_vec();
+    compose_grid(base, opts, noise, cache, buffers, &layout, rng)
+}
+
+/// A single cell holding the base image turned clockwise by a random whole
+/// angle, far enough from upright to need turning back.
+fn render_dial<R: Rng>(
+    base_buf: &[u8],
+    opts: &GenerationOptions,
+    noise: &NoiseOptions,
+    rng: &mut R,
+) -> crate::Result<(DynamicImage, u16)> {
+    opts.validate()?;
+
+    let angle: u16 = rng.random_range(30..=330);
+    let base = decode_base(base_buf, opts, noise.background)?;
+    let mut tile = rotate_image(&base, angle as f32, noise.background).to_rgba8();
+    apply_noise(&mut tile, &noise.pre_noise, rng);
+
+    Ok((DynamicImage::ImageRgba8(tile), angle))
+}
+
+/// Height of the instruction banner drawn for `opts`.
+pub(crate) fn banner_height(opts: &GenerationOptions) -> u32 {
+    (opts.cell_size / 3).max(MIN_BANNER_HEIGHT)
+}
+
+/// Copy of `sprite` with a `color` border around the 1-based `tile_index`
+/// cell, for hover previews. Accounts for the instruction banner if `opts`
+/// has one. Out of range indices return the sprite unchanged.
+pub fn highlight_tile(
+    sprite: &DynamicImage,
+    tile_index: u8,
+    opts: &GenerationOptions,
+    color: Rgba<u8>,
+) -> DynamicImage {
+    let mut out = sprite.to_rgba8();
+    if tile_index == 0 || u32::from(tile_index) > opts.tile_count() {
+        return DynamicImage::ImageRgba8(out);
+    }
+
+    let i = u32::from(tile_index) - 1;
+    let top = match opts.instruction_text {
+        Some(_) => banner_height(opts),
+        None => 0,
+    };
+    let x = (i % opts.cols) * (opts.cell_size + TILE_SPACING);
+    let y = top + (i / opts.cols) * (opts.cell_size + TILE_SPACING);
+
+    for inset in 0..HIGHLIGHT_WIDTH.min(opts.cell_size / 2) {
+        let size = opts.cell_size - 2 * inset;
+        let rect = Rect::at((x + inset) as i32, (y + inset) as i32).of_size(size, size);
+        draw_hollow_rect_mut(&mut out, rect, color);
+    }
+
+    DynamicImage::ImageRgba8(out)
+}
+
+/// Returns `sprite` below a white banner of `height` pixels with `text`
+/// drawn in dark, shrunk to fit the width if needed.
+pub fn add_instruction_banner(
+    sprite: &DynamicImage,
+    text: &str,
+    font: &FontArc,
+    height: u32,
+) -> DynamicImage {
+    let (width, sprite_height) = (sprite.width(), sprite.height());
+    let padding = height / 4;
+
+    let mut scale = PxScale::from(height as f32 * 0.5);
+    let (text_w, _) = text_size(scale, font, text);
+    let max_w = width.saturating_sub(2 * padding);
+    if text_w > max_w && text_w > 0 {
+        let shrink = max_w as f32 / text_w as f32;
+        scale = PxScale::from(scale.y * shrink);
+    }
+    let (text_w, text_h) = text_size(scale, font, text);
+
+    let mut out = RgbaImage::from_pixel(width, height + sprite_height, Rgba([255, 255, 255, 255]));
+    draw_text_mut(
+        &mut out,
+        Rgba([24, 24, 27, 255]),
+        (width.saturating_sub(text_w) / 2) as i32,
+        (height.saturating_sub(text_h) / 2) as i32,
+        scale,
+        font,
+        text,
+    );
+    imageops::replace(&mut out, &sprite.to_rgba8(), 0, height as i64);
+
+    DynamicImage::ImageRgba8(out)
+}
+
+/// Angles to rotate the base image by and the tiles a mechanic places on
+/// the grid, as `(is_correct, angle)` in cell order.
+struct TileLayout {
+    angles: Vec<f32>,
+    tiles: Vec<(bool, f32)>,
+}
+
+/// `correct_count` of `tile_count` tiles upright, the rest at distinct
+/// wrong angles.
+fn upright_layout<R: Rng>(correct_count: u8, tile_count: u32, rng: &mut R) -> TileLayout {
+    let correct_angle = 0.0;
+
+    let mut angles = Vec::with_capacity(1 + INCORRECT_ANGLES.len());
+    angles.push(correct_angle);
+    angles.extend_from_slice(&INCORRECT_ANGLES);
+
+    let mut tiles = vec![(true, correct_angle); correct_count as usize];
+    let mut others = INCORRECT_ANGLES.to_vec();
 
-    others.shuffle(&mut rng);
+    others.shuffle(rng);
 
-    for &angle in others.iter().take(8) {
+    let wrong = tile_count.saturating_sub(u32::from(correct_count)) as usize;
+    for &angle in others.iter().take(wrong) {
         tiles.push((false, angle));
     }
 
-    tiles.shuffle(&mut rng);
+    tiles.shuffle(rng);
+
+    TileLayout { angles, tiles }
+}
+
+/// Draws the tiles of `layout` into a labelled `cols` x `rows` grid and returns the
+/// 1-based numbers of the correct ones.
+fn compose_grid<R: Rng>(
+    base: Base<'_>,
+    opts: &GenerationOptions,
+    noise: &NoiseOptions,
+    cache: Option<&RotatedImageCache>,
+    buffers: Option<&SpriteBufferPool>,
+    layout: &TileLayout,
+    rng: &mut R,
+) -> crate::Result<(DynamicImage, Vec<u8>)> {
+    let TileLayout { angles, tiles } = layout;
+
+    let precomputed = match (base, cache) {
+        (Base::Encoded(base_buf, hash), Some(cache)) => {
+            let hash = hash.unwrap_or_else(|| {
+                RotatedImageCache::image_hash(base_buf, opts.cell_size, noise.background)
+            });
+            match cache.get_all(hash, angles) {
+                Some(rotated) => rotated,
+                None => {
+                    let base = decode_base(base_buf, opts, noise.background)?;
+                    cache.get_or_rotate(hash, &base, angles, noise.background)
+                }
+            }
+        }
+        (Base::Encoded(base_buf, _), None) => rotate_all(
+            &decode_base(base_buf, opts, noise.background)?,
+            angles,
+            noise.background,
+        ),
+        (Base::Decoded(img), _) => rotate_all(
+            &prepare_base(img.clone(), opts, noise.background),
+            angles,
+            noise.background,
+        ),
+    };
 
     let font = &*FONT;
-    let cols = 3;
-    let rows = 3;
-    let spacing = 4;
+    let cols = opts.cols;
+    let rows = opts.rows;
+    let spacing = TILE_SPACING;
     let sprite_width = cols * opts.cell_size + (cols - 1) * spacing;
     let sprite_height = rows * opts.cell_size + (rows - 1) * spacing;
 
-    let mut sprite_buf =
-        ImageBuffer::from_pixel(sprite_width, sprite_height, Rgba([255, 255, 255, 255]));
-
-    let mut correct_number = 0;
+    let mut sprite_buf = match buffers {
+        Some(pool) => pool.acquire(sprite_width, sprite_height, noise.background),
+        None => ImageBuffer::from_pixel(sprite_width, sprite_height, noise.background),
+    };
 
-    for (i, (is_correct, angle)) in tiles.iter().enumerate() {
-        // Create and draw each tile
+    // Randomness is drawn up front so tiles can be rendered independently.
+    let mut plans = Vec::with_capacity(tiles.len());
+    for (is_correct, angle) in tiles {
         let tile_scale = 0.5 + rng.random_range(0.0..0.3);
-        let shrink_size = (opts.cell_size as f32 * tile_scale) as u32;
         let rotated = precomputed
             .iter()
             .find(|(a, _)| (*a - *angle).abs() < f32::EPSILON)
-            .map(|(_, img)| img)
+            .map(|(_, img)| img.as_ref())
             .ok_or_else(|| CaptchaError::Internal("missing precomputed angle".into()))?;
 
-        let mut tile = image::imageops::resize(
+        let shrink_size = (opts.cell_size as f32 * tile_scale) as u32;
+        plans.push(TilePlan {
             rotated,
             shrink_size,
-            shrink_size,
-            imageops::FilterType::Lanczos3,
-        );
+            flip: rng.random_bool(0.5),
+            color: opts.color_jitter.map(|jitter| jitter.sample(rng)),
+            grayscale: !is_correct && rng.random_bool(opts.grayscale_tile_probability),
+            warp: opts
+                .perspective_warp
+                .then(|| warp_corners(shrink_size as f32, rng)),
+            noise_seed: rng.random(),
+        });
+    }
 
-        let should_flip = rng.random_bool(0.5);
-        if should_flip {
+    let render = |plan: &TilePlan| {
+        let mut tile = shrink_tile(plan.rotated, plan.shrink_size, opts.sharpen_after_resize);
+        if plan.flip {
             tile = imageops::flip_horizontal(&tile);
         }
+        if let Some(color) = &plan.color {
+            color.apply(&mut tile);
+        }
+        if plan.grayscale {
+            tile = DynamicImage::ImageRgba8(tile).grayscale().into_rgba8();
+        }
+        if let Some(corners) = plan.warp {
+            tile = perspective_warp(&tile, corners, noise.background);
+        }
+
+        let mut tile_rng = StdRng::seed_from_u64(plan.noise_seed);
+        apply_noise(&mut tile, &noise.pre_noise, &mut tile_rng);
+        tile
+    };
+
+    #[cfg(feature = "parallel")]
+    let rendered: Vec<RgbaImage> = if plans.len() >= PARALLEL_MIN_TILES {
+        plans.par_iter().map(render).collect()
+    } else {
+        plans.iter().map(render).collect()
+    };
+    #[cfg(not(feature = "parallel"))]
+    let rendered: Vec<RgbaImage> = plans.iter().map(render).collect();
+
+    let mut correct_numbers = Vec::new();
+
+    for (i, ((is_correct, _), tile)) in tiles.iter().zip(&rendered).enumerate() {
+        let shrink_size = plans[i].shrink_size;
 
         let col = i as u32 % cols;
         let row = i as u32 / cols;
@@ -152,20 +653,27 @@ pub fn create_sprite(
         let base_x = col * (opts.cell_size + spacing);
         let base_y = row * (opts.cell_size + spacing);
 
-        let offset_x = (opts.cell_size - shrink_size) / 2;
-        let offset_y = (opts.cell_size - shrink_size) / 2;
+        let offset_x = opts.cell_size.saturating_sub(shrink_size) / 2;
+        let offset_y = opts.cell_size.saturating_sub(shrink_size) / 2;
 
-        let jitter_limit_x = offset_x as i32;
-        let jitter_limit_y = offset_y as i32;
+        // Jitter within [0, 2 * offset] keeps the tile inside its cell.
+        let jitter_x = rng.random_range(0..=offset_x.saturating_mul(2));
+        let jitter_y = rng.random_range(0..=offset_y.saturating_mul(2));
 
-        let jitter_x = rng.random_range(-jitter_limit_x..=jitter_limit_x);
-        let jitter_y = rng.random_range(-jitter_limit_y..=jitter_limit_y);
+        let draw_x = base_x.saturating_add(jitter_x);
+        let draw_y = base_y.saturating_add(jitter_y);
 
-        let draw_x = (base_x as i32 + offset_x as i32 + jitter_x) as u32;
-        let draw_y = (base_y as i32 + offset_y as i32 + jitter_y) as u32;
+        if draw_x.saturating_add(tile.width()) > sprite_width
+            || draw_y.saturating_add(tile.height()) > sprite_height
+        {
+            return Err(CaptchaError::Internal(format!(
+                "tile {} at ({draw_x}, {draw_y}) exceeds sprite bounds {sprite_width}x{sprite_height}",
+                i + 1
+            )));
+        }
 
         sprite_buf
-            .copy_from(&tile, draw_x, draw_y)
+            .copy_from(tile, draw_x, draw_y)
             .map_err(|e| CaptchaError::Internal(format!("copy tile into sprite buffer: {e}")))?;
 
         // Draw the number label
@@ -198,22 +706,116 @@ pub fn create_sprite(
         );
 
         if *is_correct {
-            correct_number = (i + 1) as u8;
+            correct_numbers.push((i + 1) as u8);
+        }
+    }
+
+    if opts.draw_grid_lines {
+        draw_grid_lines(&mut sprite_buf, cols, rows, opts.cell_size, spacing);
+    }
+
+    Ok((DynamicImage::ImageRgba8(sprite_buf), correct_numbers))
+}
+
+/// Fills each `spacing` wide gap between cells with one-pixel segments.
+fn draw_grid_lines(sprite_buf: &mut RgbaImage, cols: u32, rows: u32, cell_size: u32, spacing: u32) {
+    let (width, height) = (sprite_buf.width() as f32, sprite_buf.height() as f32);
+
+    for gap in 1..cols {
+        let start = gap * (cell_size + spacing) - spacing;
+        for x in start..start + spacing {
+            let x = x as f32;
+            draw_line_segment_mut(sprite_buf, (x, 0.0), (x, height - 1.0), GRID_LINE_COLOR);
         }
     }
+    for gap in 1..rows {
+        let start = gap * (cell_size + spacing) - spacing;
+        for y in start..start + spacing {
+            let y = y as f32;
+            draw_line_segment_mut(sprite_buf, (0.0, y), (width - 1.0, y), GRID_LINE_COLOR);
+        }
+    }
+}
+
+struct TilePlan<'a> {
+    rotated: &'a RgbaImage,
+    shrink_size: u32,
+    flip: bool,
+    color: Option<ColorJitter>,
+    grayscale: bool,
+    /// Target corners for [`perspective_warp`], clockwise from top-left.
+    warp: Option<[(f32, f32); 4]>,
+    noise_seed: u64,
+}
+
+/// Corners of a `size` tile, each pulled inwards by up to [`MAX_WARP`].
+fn warp_corners<R: Rng>(size: f32, rng: &mut R) -> [(f32, f32); 4] {
+    let max = size * MAX_WARP;
+    let mut inset = || rng.random_range(0.0..=max);
 
-    Ok((DynamicImage::ImageRgba8(sprite_buf), correct_number))
+    [
+        (inset(), inset()),
+        (size - inset(), inset()),
+        (size - inset(), size - inset()),
+        (inset(), size - inset()),
+    ]
 }
 
 fn sprite_to_base64(buf: &[u8], mime: &str) -> String {
     format!("data:{};base64,{}", mime, BASE64_STANDARD.encode(buf))
 }
 
+/// Decodes a JPEG, PNG or WebP base image, detected from its magic bytes.
+/// Other formats are rejected as [`CaptchaError::InvalidInput`].
+/// Transparent bases are flattened onto `bg` so tiles blend into the sprite.
+fn decode_base(
+    base_buf: &[u8],
+    opts: &GenerationOptions,
+    bg: Rgba<u8>,
+) -> crate::Result<DynamicImage> {
+    let format = validate_image_format(base_buf)?;
+    let mut reader = ImageReader::with_format(Cursor::new(base_buf), format);
+    if let Some(limits) = opts.limits.clone() {
+        reader.limits(limits);
+    } else {
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(4096);
+        limits.max_image_height = Some(4096);
+        limits.max_alloc = Some(128 * 1024 * 1024);
+
+        reader.limits(limits);
+    }
+
+    let decoded = reader.decode().map_err(CaptchaError::Decode)?;
+
+    Ok(prepare_base(decoded, opts, bg))
+}
+
+/// Flattens a decoded base onto `bg` and scales it to one cell.
+fn prepare_base(img: DynamicImage, opts: &GenerationOptions, bg: Rgba<u8>) -> DynamicImage {
+    flatten_alpha(img, bg).resize_exact(
+        opts.cell_size,
+        opts.cell_size,
+        imageops::FilterType::Nearest,
+    )
+}
+
+/// Scales a rotated tile down to `size`. Lanczos3 softens edges, `sharpen`
+/// restores them afterwards.
+fn shrink_tile(rotated: &RgbaImage, size: u32, sharpen_tile: bool) -> RgbaImage {
+    let tile = imageops::resize(rotated, size, size, imageops::FilterType::Lanczos3);
+    if sharpen_tile {
+        sharpen(&DynamicImage::ImageRgba8(tile)).into_rgba8()
+    } else {
+        tile
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::challenge::generate;
-    use crate::image::NoiseOptions;
+    use crate::challenge::{ChallengeBuilder, ChallengeType};
+    use image::GenericImageView;
 
     const SECRET: &[u8] = b"secret-key";
 
@@ -226,11 +828,17 @@ mod tests {
         let base = load_sample_image();
         let opts = GenerationOptions {
             cell_size: 120,
-            sprite_format: SpriteFormat::Jpeg { quality: 60 },
-            limits: None,
+            sprite_format: SpriteFormat::Jpeg {
+                quality: 60,
+                progressive: false,
+                chroma_subsampling: JpegSubsampling::Yuv420,
+            },
+            ..Default::default()
         };
 
-        let ch = generate::<SpriteUri>(&base, SECRET, &opts, NoiseOptions::default())
+        let ch = ChallengeBuilder::new(ChallengeType::Rotation)
+            .options(opts.clone())
+            .build::<SpriteUri>(&base, SECRET)
             .expect("jpeg generation failed");
         assert!(ch.sprite.0.starts_with("data:image/jpeg;base64,"));
 
@@ -258,12 +866,14 @@ mod tests {
             cell_size: 120,
             sprite_format: SpriteFormat::Webp {
                 quality: 75,
-                lossless: false,
+                method: 4,
             },
-            limits: None,
+            ..Default::default()
         };
 
-        let ch = generate::<SpriteUri>(&base, SECRET, &opts, NoiseOptions::default())
+        let ch = ChallengeBuilder::new(ChallengeType::Rotation)
+            .options(opts.clone())
+            .build::<SpriteUri>(&base, SECRET)
             .expect("webp generation failed");
         assert!(ch.sprite.0.starts_with("data:image/webp;base64,"));
 
@@ -289,13 +899,20 @@ mod tests {
         let base = load_sample_image();
         let opts = GenerationOptions {
             cell_size: 150,
-            sprite_format: SpriteFormat::Jpeg { quality: 70 },
-            limits: None,
+            sprite_format: SpriteFormat::Jpeg {
+                quality: 70,
+                progressive: false,
+                chroma_subsampling: JpegSubsampling::Yuv420,
+            },
+            ..Default::default()
         };
-        let ch = generate::<SpriteBinary>(&base, SECRET, &opts, NoiseOptions::default())
+        let ch = ChallengeBuilder::new(ChallengeType::Rotation)
+            .options(opts.clone())
+            .build::<SpriteBinary>(&base, SECRET)
             .expect("jpeg binary generation failed");
 
         assert_eq!(ch.sprite.mime, "image/jpeg");
+        assert_eq!(ch.sprite.mime, opts.sprite_format.mime_type());
         assert!(!ch.sprite.bytes.is_empty());
 
         // fs::write(Path::new("examples/exampleX.jpeg"), &ch.sprite.bytes)
@@ -315,14 +932,17 @@ mod tests {
             cell_size: 150,
             sprite_format: SpriteFormat::Webp {
                 quality: 70,
-                lossless: false,
+                method: 4,
             },
-            limits: None,
+            ..Default::default()
         };
-        let ch = generate::<SpriteBinary>(&base, SECRET, &opts, NoiseOptions::default())
+        let ch = ChallengeBuilder::new(ChallengeType::Rotation)
+            .options(opts.clone())
+            .build::<SpriteBinary>(&base, SECRET)
             .expect("webp binary generation failed");
 
         assert_eq!(ch.sprite.mime, "image/webp");
+        assert_eq!(ch.sprite.mime, opts.sprite_format.mime_type());
         assert!(!ch.sprite.bytes.is_empty());
 
         // fs::write(Path::new("examples/exampleX.webp"), &ch.sprite.bytes)
@@ -334,4 +954,503 @@ mod tests {
             .decode()
             .expect("decode webp binary");
     }
+
+    #[test]
+    fn test_sprite_binary_progressive_jpeg_and_decode() {
+        let base = load_sample_image();
+        let opts = GenerationOptions {
+            cell_size: 120,
+            sprite_format: SpriteFormat::Jpeg {
+                quality: 70,
+                progressive: true,
+                chroma_subsampling: JpegSubsampling::Yuv420,
+            },
+            ..Default::default()
+        };
+        let ch = ChallengeBuilder::new(ChallengeType::Rotation)
+            .options(opts.clone())
+            .build::<SpriteBinary>(&base, SECRET)
+            .expect("progressive jpeg generation failed");
+
+        assert_eq!(ch.sprite.mime, "image/jpeg");
+        // SOF2 marker, progressive DCT
+        assert!(ch.sprite.bytes.windows(2).any(|w| w == [0xFF, 0xC2]));
+
+        let img = ImageReader::new(Cursor::new(&ch.sprite.bytes))
+            .with_guessed_format()
+            .expect("guess jpeg format")
+            .decode()
+            .expect("decode progressive jpeg binary");
+
+        assert_eq!(img.dimensions(), ch.sprite_dbg.dimensions());
+    }
+
+    #[test]
+    fn test_sprite_binary_webp_lossless_and_decode() {
+        let base = load_sample_image();
+        let opts = GenerationOptions {
+            cell_size: 100,
+            sprite_format: SpriteFormat::WebpLossless {
+                compression_level: 0,
+            },
+            ..Default::default()
+        };
+        let ch = ChallengeBuilder::new(ChallengeType::Rotation)
+            .options(opts.clone())
+            .build::<SpriteBinary>(&base, SECRET)
+            .expect("webp lossless generation failed");
+
+        assert_eq!(ch.sprite.mime, "image/webp");
+
+        let img = ImageReader::new(Cursor::new(&ch.sprite.bytes))
+            .with_guessed_format()
+            .expect("guess webp format")
+            .decode()
+            .expect("decode webp lossless binary");
+
+        assert_eq!(img.to_rgb8(), ch.sprite_dbg.to_rgb8());
+    }
+
+    #[test]
+    fn test_sprite_uses_noise_background() {
+        let base = load_sample_image();
+        let opts = GenerationOptions::default();
+        let noise = NoiseOptions::for_dark_background();
+
+        let (sprite, _) =
+            create_sprite(&base, &opts, &noise, None, None).expect("sprite creation failed");
+        let sprite = sprite.to_rgba8();
+
+        // Spacing gap between the first two columns is never covered by tiles
+        assert_eq!(*sprite.get_pixel(opts.cell_size + 1, 0), noise.background);
+    }
+
+    #[test]
+    fn test_create_sprite_from_image() {
+        let base = image::load_from_memory(&load_sample_image()).unwrap();
+        let opts = GenerationOptions {
+            cell_size: 64,
+            ..Default::default()
+        };
+
+        let (sprite, correct) = create_sprite_from_image(&base, &opts, &NoiseOptions::default())
+            .expect("sprite from decoded image failed");
+        assert_eq!(sprite.dimensions(), (3 * 64 + 2 * 4, 3 * 64 + 2 * 4));
+        assert!((1..=9).contains(&correct));
+    }
+
+    #[test]
+    fn test_grayscale_tiles_skip_correct_one() {
+        let base = load_sample_image();
+        let opts = GenerationOptions {
+            cell_size: 64,
+            grayscale_tile_probability: crate::MAX_GRAYSCALE_TILE_PROBABILITY,
+            ..Default::default()
+        };
+        let quiet = crate::NoiseLayer {
+            count: 0,
+            blur_sigma: 0.0,
+            ..Default::default()
+        };
+        let noise = NoiseOptions {
+            pre_noise: quiet,
+            post_noise: quiet,
+            ..Default::default()
+        };
+
+        let mut gray_tiles = 0;
+        for seed in 0..8 {
+            let (sprite, correct) = create_sprite_seeded(&base, &opts, &noise, seed).unwrap();
+            let sprite = sprite.to_rgba8();
+            for i in 0..9u32 {
+                let x0 = (i % 3) * (64 + TILE_SPACING);
+                let y0 = (i / 3) * (64 + TILE_SPACING);
+                // The middle of a cell is always covered by its tile
+                let gray = (28..36).all(|y| {
+                    (28..36).all(|x| {
+                        let [r, g, b, _] = sprite.get_pixel(x0 + x, y0 + y).0;
+                        r == g && g == b
+                    })
+                });
+                if i + 1 == correct as u32 {
+                    assert!(!gray, "correct tile is gray (seed {seed})");
+                }
+                gray_tiles += u32::from(gray);
+            }
+        }
+        assert!(gray_tiles > 0);
+    }
+
+    #[test]
+    fn test_grayscale_probability_is_capped() {
+        let opts = |p| GenerationOptions {
+            grayscale_tile_probability: p,
+            ..Default::default()
+        };
+
+        assert!(
+            opts(crate::MAX_GRAYSCALE_TILE_PROBABILITY)
+                .validate()
+                .is_ok()
+        );
+        assert!(matches!(
+            opts(0.75).validate(),
+            Err(CaptchaError::InvalidInput(_))
+        ));
+        assert!(opts(1.0).validate().is_err());
+    }
+
+    #[test]
+    fn test_grid_size_follows_cols_and_rows() {
+        let base = load_sample_image();
+        let opts = GenerationOptions {
+            cell_size: 64,
+            cols: 2,
+            rows: 2,
+            ..Default::default()
+        };
+
+        let (sprite, correct) = create_sprite_seeded(&base, &opts, &NoiseOptions::default(), 3)
+            .expect("2x2 sprite creation failed");
+        assert_eq!(sprite.dimensions(), (2 * 64 + 4, 2 * 64 + 4));
+        assert!((1..=4).contains(&correct));
+
+        let opts = GenerationOptions {
+            cols: 4,
+            rows: 4,
+            ..opts
+        };
+        let err = create_sprite(&base, &opts, &NoiseOptions::default(), None, None).unwrap_err();
+        assert_eq!(
+            err,
+            CaptchaError::InvalidInput(
+                "a 4x4 grid needs 15 incorrect angles, only 11 available".into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_grid_lines_fill_tile_gaps() {
+        let base = load_sample_image();
+        let opts = GenerationOptions {
+            cell_size: 64,
+            draw_grid_lines: true,
+            ..Default::default()
+        };
+
+        let (sprite, _) = create_sprite(&base, &opts, &NoiseOptions::default(), None, None)
+            .expect("sprite creation failed");
+        let sprite = sprite.to_rgba8();
+
+        for offset in 0..4 {
+            assert_eq!(*sprite.get_pixel(64 + offset, 10), GRID_LINE_COLOR);
+            assert_eq!(*sprite.get_pixel(10, 2 * 64 + 4 + offset), GRID_LINE_COLOR);
+        }
+    }
+
+    #[test]
+    fn test_highlight_tile_borders_requested_cell() {
+        let opts = GenerationOptions {
+            cell_size: 64,
+            ..Default::default()
+        };
+        let sprite = DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            3 * 64 + 2 * 4,
+            3 * 64 + 2 * 4,
+            Rgba([255, 255, 255, 255]),
+        ));
+        let red = Rgba([220, 0, 0, 255]);
+
+        // Tile 5 is the centre cell.
+        let out = highlight_tile(&sprite, 5, &opts, red).to_rgba8();
+        assert_eq!(*out.get_pixel(68, 68), red);
+        assert_eq!(*out.get_pixel(68 + 63, 68 + 2), red);
+        assert_eq!(*out.get_pixel(68 + 32, 68 + 32), Rgba([255, 255, 255, 255]));
+        assert_eq!(*out.get_pixel(10, 10), Rgba([255, 255, 255, 255]));
+
+        let untouched = highlight_tile(&sprite, 10, &opts, red);
+        assert_eq!(untouched.as_bytes(), sprite.as_bytes());
+    }
+
+    #[test]
+    fn test_instruction_banner_prepends_rows() {
+        let base = load_sample_image();
+        let opts = GenerationOptions {
+            cell_size: 64,
+            instruction_text: Some("Select the upright image".into()),
+            ..Default::default()
+        };
+
+        let ch = ChallengeBuilder::new(ChallengeType::Rotation)
+            .options(opts)
+            .build::<SpriteBinary>(&base, SECRET)
+            .expect("banner generation failed");
+        let sprite = ch.sprite_dbg.to_rgb8();
+        assert_eq!(sprite.dimensions(), (3 * 64 + 2 * 4, 3 * 64 + 2 * 4 + 24));
+
+        let banner = imageops::crop_imm(&sprite, 0, 0, sprite.width(), 24).to_image();
+        assert_eq!(*banner.get_pixel(0, 0), image::Rgb([255, 255, 255]));
+        assert!(banner.pixels().any(|px| px.0[0] < 128));
+    }
+
+    #[test]
+    fn test_transparent_png_base_is_flattened() {
+        let mut base = RgbaImage::from_pixel(64, 64, Rgba([0, 0, 0, 0]));
+        for x in 16..48 {
+            for y in 16..48 {
+                base.put_pixel(x, y, Rgba([200, 40, 40, 255]));
+            }
+        }
+        let mut png = Vec::new();
+        base.write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+
+        let opts = GenerationOptions {
+            cell_size: 64,
+            ..Default::default()
+        };
+        let noise = NoiseOptions::for_dark_background();
+
+        let decoded = decode_base(&png, &opts, noise.background).unwrap();
+        let decoded = decoded.to_rgba8();
+        assert_eq!(*decoded.get_pixel(0, 0), noise.background);
+        assert_eq!(*decoded.get_pixel(32, 32), Rgba([200, 40, 40, 255]));
+
+        let (_, correct) = create_sprite(&png, &opts, &noise, None, None).unwrap();
+        assert!((1..=9).contains(&correct));
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_create_sprite_from_path() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/sample1.jpg");
+        let opts = GenerationOptions {
+            cell_size: 64,
+            ..Default::default()
+        };
+
+        let (sprite, correct) = create_sprite_from_path(&path, &opts, &NoiseOptions::default())
+            .expect("mmap sprite creation failed");
+        assert_eq!(sprite.dimensions(), (3 * 64 + 2 * 4, 3 * 64 + 2 * 4));
+        assert!((1..=9).contains(&correct));
+
+        let missing = path.with_file_name("does-not-exist.jpg");
+        let err = create_sprite_from_path(&missing, &opts, &NoiseOptions::default()).unwrap_err();
+        assert!(matches!(err, CaptchaError::Io(_)));
+    }
+
+    #[test]
+    fn test_create_sprite_rejects_oversized_cell() {
+        let base = load_sample_image();
+        let opts = GenerationOptions {
+            cell_size: 10_000,
+            ..Default::default()
+        };
+
+        let err = create_sprite(&base, &opts, &NoiseOptions::default(), None, None).unwrap_err();
+        assert!(
+            matches!(err, CaptchaError::InvalidInput(msg) if msg == "cell_size must be at most 1024")
+        );
+    }
+
+    #[test]
+    fn test_create_sprite_seeded_is_deterministic() {
+        let base = load_sample_image();
+        let opts = GenerationOptions {
+            cell_size: 64,
+            ..Default::default()
+        };
+        let noise = NoiseOptions::default();
+
+        let (a, a_correct) = create_sprite_seeded(&base, &opts, &noise, 7).unwrap();
+        let (b, b_correct) = create_sprite_seeded(&base, &opts, &noise, 7).unwrap();
+
+        assert_eq!(a_correct, b_correct);
+        assert_eq!(a.as_bytes(), b.as_bytes());
+    }
+
+    #[test]
+    fn test_create_sprite_rejects_malformed_jpeg() {
+        let base = load_sample_image();
+        let opts = GenerationOptions {
+            cell_size: 32,
+            ..Default::default()
+        };
+
+        let result = create_sprite(
+            b"\xFF\xD8\xFF\xE0garbage",
+            &opts,
+            &NoiseOptions::default(),
+            None,
+            None,
+        );
+        assert!(matches!(result, Err(CaptchaError::Decode(_))));
+
+        // The decoder may pad a truncated scan, either outcome is fine as long as it returns.
+        let truncated = &base[..base.len() / 8];
+        let result = create_sprite(truncated, &opts, &NoiseOptions::default(), None, None);
+        assert!(matches!(result, Ok(_) | Err(CaptchaError::Decode(_))));
+    }
+
+    #[test]
+    fn test_create_sprite_rejects_unsupported_format() {
+        let opts = GenerationOptions {
+            cell_size: 32,
+            ..Default::default()
+        };
+
+        let gif = b"GIF89a\x01\x00\x01\x00\x00\x00\x00;";
+        let result = create_sprite(gif, &opts, &NoiseOptions::default(), None, None);
+        assert!(matches!(
+            result,
+            Err(CaptchaError::InvalidInput(msg)) if msg == "expected JPEG, PNG or WebP image, got GIF"
+        ));
+
+        let result = create_sprite(b"", &opts, &NoiseOptions::default(), None, None);
+        assert!(matches!(result, Err(CaptchaError::InvalidInput(_))));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_sprite_file_writes_encoded_bytes() {
+        let base = load_sample_image();
+        let opts = GenerationOptions {
+            cell_size: 64,
+            sprite_format: SpriteFormat::Webp {
+                quality: 60,
+                method: 4,
+            },
+            ..Default::default()
+        };
+
+        let dir = std::env::temp_dir();
+        let lossless = SpriteFormat::WebpLossless {
+            compression_level: 1,
+        };
+        for (format, ext) in [
+            (opts.sprite_format, "webp"),
+            (lossless, "webp"),
+            (SpriteFormat::default(), "jpg"),
+        ] {
+            let ch = ChallengeBuilder::new(ChallengeType::Rotation)
+                .options(GenerationOptions {
+                    sprite_format: format,
+                    ..opts.clone()
+                })
+                .build::<SpriteFile>(&base, SECRET)
+                .expect("file generation failed");
+
+            let path = ch.sprite.write_to(&dir).await.expect("write sprite file");
+            let bytes = std::fs::read(&path).expect("read sprite file");
+            std::fs::remove_file(&path).expect("remove sprite file");
+
+            assert_eq!(path.parent(), Some(dir.as_path()));
+            assert_eq!(path.extension().unwrap(), ext);
+            assert_eq!(
+                SpriteFormat::detect_from_bytes(&bytes).map(|f| f.mime_type()),
+                Some(format.mime_type())
+            );
+        }
+    }
+
+    #[test]
+    fn test_sharpen_after_resize_steepens_tile_edges() {
+        let rotated = RgbaImage::from_fn(96, 96, |x, _| {
+            let v = if x < 48 { 60 } else { 200 };
+            Rgba([v, v, v, 255])
+        });
+        let steepest = |tile: &RgbaImage| {
+            (1..tile.width())
+                .map(|x| tile.get_pixel(x, 20).0[0].abs_diff(tile.get_pixel(x - 1, 20).0[0]))
+                .max()
+                .unwrap()
+        };
+
+        let soft = shrink_tile(&rotated, 41, false);
+        let sharp = shrink_tile(&rotated, 41, true);
+        assert_eq!(sharp.dimensions(), (41, 41));
+        assert!(steepest(&sharp) > steepest(&soft));
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn test_sprite_bytes_target() {
+        let base = load_sample_image();
+        let opts = GenerationOptions {
+            cell_size: 64,
+            ..Default::default()
+        };
+
+        let ch = ChallengeBuilder::new(ChallengeType::Rotation)
+            .options(opts.clone())
+            .build::<bytes::Bytes>(&base, SECRET)
+            .expect("bytes generation failed");
+
+        assert!(ch.sprite.starts_with(&[0xFF, 0xD8, 0xFF]));
+    }
+
+    #[test]
+    fn test_sprite_shared_vec_target() {
+        let base = load_sample_image();
+        let opts = GenerationOptions {
+            cell_size: 64,
+            ..Default::default()
+        };
+
+        let ch = ChallengeBuilder::new(ChallengeType::Rotation)
+            .options(opts.clone())
+            .build::<Arc<Vec<u8>>>(&base, SECRET)
+            .expect("shared vec generation failed");
+
+        let cached = Arc::clone(&ch.sprite);
+        assert_eq!(Arc::strong_count(&ch.sprite), 2);
+        assert!(cached.starts_with(&[0xFF, 0xD8, 0xFF]));
+    }
+
+    #[test]
+    fn test_sprite_format_displays_mime_type() {
+        assert_eq!(SpriteFormat::default().to_string(), "image/jpeg");
+        let lossless = SpriteFormat::WebpLossless {
+            compression_level: 6,
+        };
+        assert_eq!(format!("{lossless}"), "image/webp");
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_sprite_format_deserializes_flat_and_rust_forms() {
+        let flat: SpriteFormat = serde_json::from_str(
+            r#"{"type": "jpeg", "quality": 70, "chroma_subsampling": "4:4:4"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            flat,
+            SpriteFormat::Jpeg {
+                quality: 70,
+                progressive: false,
+                chroma_subsampling: JpegSubsampling::Yuv444,
+            }
+        ));
+
+        let tagged: SpriteFormat =
+            serde_json::from_str(r#"{"Webp": {"quality": 60, "method": 6}}"#).unwrap();
+        assert!(matches!(
+            tagged,
+            SpriteFormat::Webp {
+                quality: 60,
+                method: 6
+            }
+        ));
+
+        let lossless: SpriteFormat =
+            serde_json::from_str(r#"{"type": "webp-lossless", "compression_level": 9}"#).unwrap();
+        assert!(matches!(
+            lossless,
+            SpriteFormat::WebpLossless {
+                compression_level: 9
+            }
+        ));
+
+        assert!(serde_json::from_str::<SpriteFormat>(r#"{"type": "gif", "quality": 70}"#).is_err());
+    }
 }
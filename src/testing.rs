@@ -0,0 +1,193 @@
+use crate::registry::{ChallengeRegistry, RegistryCheckResult};
+use crate::utils::Clock;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/// [`Clock`] that only moves when told to, for TTL tests without sleeping.
+#[derive(Debug, Default)]
+pub struct MockClock(AtomicU64);
+
+impl MockClock {
+    pub fn new(now_secs: u64) -> Self {
+        Self(AtomicU64::new(now_secs))
+    }
+
+    pub fn set(&self, now_secs: u64) {
+        self.0.store(now_secs, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, secs: u64) {
+        self.0.fetch_add(secs, Ordering::Relaxed);
+    }
+}
+
+impl Clock for MockClock {
+    fn now_secs(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Registry that answers `check` with a fixed result and records every call,
+/// built with [`MockRegistryBuilder`].
+#[derive(Debug)]
+pub struct MockChallengeRegistry {
+    check_result: RegistryCheckResult,
+    calls: Mutex<Calls>,
+}
+
+#[derive(Debug, Default)]
+struct Calls {
+    registered: Vec<String>,
+    checked: Vec<String>,
+    verified: Vec<String>,
+    attempts: Vec<(String, bool)>,
+}
+
+impl MockChallengeRegistry {
+    pub fn builder() -> MockRegistryBuilder {
+        MockRegistryBuilder::default()
+    }
+
+    /// Ids passed to `register`, in call order.
+    pub fn registered_ids(&self) -> Vec<String> {
+        self.calls().registered.clone()
+    }
+
+    /// Ids passed to `check`, in call order.
+    pub fn checked_ids(&self) -> Vec<String> {
+        self.calls().checked.clone()
+    }
+
+    pub fn verification_count(&self) -> usize {
+        self.calls().verified.len()
+    }
+
+    /// `(id, success)` pairs passed to `note_attempt`, in call order.
+    pub fn attempts(&self) -> Vec<(String, bool)> {
+        self.calls().attempts.clone()
+    }
+
+    fn calls(&self) -> MutexGuard<'_, Calls> {
+        // Only appends happen under the lock, a panic can't corrupt it.
+        self.calls.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for MockChallengeRegistry {
+    fn default() -> Self {
+        MockRegistryBuilder::default().build()
+    }
+}
+
+impl ChallengeRegistry for MockChallengeRegistry {
+    fn register(&self, id: &str) {
+        self.calls().registered.push(id.to_owned());
+    }
+
+    fn check(&self, id: &str) -> RegistryCheckResult {
+        self.calls().checked.push(id.to_owned());
+        self.check_result.clone()
+    }
+
+    fn verify(&self, id: &str) {
+        self.calls().verified.push(id.to_owned());
+    }
+
+    fn note_attempt(&self, id: &str, success: bool) {
+        self.calls().attempts.push((id.to_owned(), success));
+    }
+}
+
+/// Registry that accepts registrations but reports every id as
+/// [`RegistryCheckResult::NotRegistered`], for exercising rejection paths.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FailingChallengeRegistry;
+
+impl ChallengeRegistry for FailingChallengeRegistry {
+    fn register(&self, _id: &str) {}
+
+    fn check(&self, _id: &str) -> RegistryCheckResult {
+        RegistryCheckResult::NotRegistered
+    }
+
+    fn verify(&self, _id: &str) {}
+
+    fn note_attempt(&self, _id: &str, _success: bool) {}
+}
+
+#[derive(Debug)]
+pub struct MockRegistryBuilder {
+    check_result: RegistryCheckResult,
+}
+
+impl Default for MockRegistryBuilder {
+    fn default() -> Self {
+        Self {
+            check_result: RegistryCheckResult::Ok {
+                remaining_attempts: 1,
+            },
+        }
+    }
+}
+
+impl MockRegistryBuilder {
+    /// Result every `check` call returns, `Ok` with one attempt left by default.
+    pub fn check_result(mut self, result: RegistryCheckResult) -> Self {
+        self.check_result = result;
+        self
+    }
+
+    pub fn build(self) -> MockChallengeRegistry {
+        MockChallengeRegistry {
+            check_result: self.check_result,
+            calls: Mutex::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CaptchaError, CaptchaManager, GenerationOptions, NoiseOptions, SpriteBinary};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_mock_registry_records_calls() {
+        let registry = MockChallengeRegistry::builder()
+            .check_result(RegistryCheckResult::AlreadyVerified)
+            .build();
+
+        registry.register("a");
+        registry.register("b");
+        assert_eq!(registry.check("a"), RegistryCheckResult::AlreadyVerified);
+        registry.note_attempt("a", false);
+        registry.verify("b");
+
+        assert_eq!(registry.registered_ids(), ["a", "b"]);
+        assert_eq!(registry.checked_ids(), ["a"]);
+        assert_eq!(registry.attempts(), [("a".to_owned(), false)]);
+        assert_eq!(registry.verification_count(), 1);
+    }
+
+    #[test]
+    fn test_failing_registry_rejects_correct_answer() {
+        let mgr = CaptchaManager::new(
+            "secret".into(),
+            60,
+            NoiseOptions::default(),
+            Some(Arc::new(FailingChallengeRegistry)),
+            GenerationOptions {
+                cell_size: 64,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let ch = mgr.generate_challenge::<SpriteBinary>().unwrap();
+        assert!(matches!(
+            mgr.verify_challenge(&ch.challenge_id, ch.correct_number),
+            Err(CaptchaError::Registry(RegistryCheckResult::NotRegistered))
+        ));
+    }
+}
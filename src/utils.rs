@@ -1,5 +1,24 @@
+#[cfg(any(feature = "render", not(feature = "no-std")))]
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Source of the current Unix time in seconds, used to stamp and expire challenges.
+pub trait Clock: Send + Sync {
+    fn now_secs(&self) -> u64;
+}
+
+/// [`Clock`] backed by [`SystemTime`].
+#[cfg(any(feature = "render", not(feature = "no-std")))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+#[cfg(any(feature = "render", not(feature = "no-std")))]
+impl Clock for SystemClock {
+    fn now_secs(&self) -> u64 {
+        get_timestamp()
+    }
+}
+
+#[cfg(any(feature = "render", not(feature = "no-std")))]
 pub fn get_timestamp() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -1,7 +1,10 @@
 use geronimo_captcha::{
-    CaptchaError, CaptchaManager, GenerationOptions, NoiseOptions, SpriteFormat, SpriteUri,
+    CaptchaError, CaptchaManager, GenerationOptions, JpegSubsampling, NoiseLayer, NoiseOptions,
+    ParsedChallenge, SpriteFormat, SpriteUri,
 };
 use proptest::prelude::*;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
 use std::thread::sleep;
 use std::time::Duration;
 
@@ -15,10 +18,13 @@ fn build_mgr(ttl: u64, cell_size: u32, jpeg_quality: u8) -> CaptchaManager {
             cell_size,
             sprite_format: SpriteFormat::Jpeg {
                 quality: jpeg_quality,
+                progressive: false,
+                chroma_subsampling: JpegSubsampling::Yuv420,
             },
-            limits: None,
+            ..Default::default()
         },
     )
+    .expect("valid options")
 }
 
 fn prop_malformed_is_rejected(id: &str) -> bool {
@@ -43,6 +49,43 @@ fn prop_ttl_zero_expires(idx: u8) -> bool {
     }
 }
 
+/// One noise-free manager per cell size. Its rotation cache saves decoding
+/// the base image on every case.
+fn cached_mgr(cell: u32) -> CaptchaManager {
+    static MANAGERS: OnceLock<Mutex<HashMap<u32, CaptchaManager>>> = OnceLock::new();
+    let mut managers = MANAGERS.get_or_init(Mutex::default).lock().unwrap();
+    managers
+        .entry(cell)
+        .or_insert_with(|| {
+            let quiet = NoiseLayer {
+                count: 0,
+                blur_sigma: 0.0,
+                ..Default::default()
+            };
+            let noise = NoiseOptions {
+                pre_noise: quiet,
+                post_noise: quiet,
+                ..Default::default()
+            };
+            let opts = GenerationOptions {
+                cell_size: cell,
+                ..Default::default()
+            };
+            CaptchaManager::new("s".into(), 60, noise, None, opts).expect("valid options")
+        })
+        .clone()
+}
+
+fn prop_challenge_id_parses(cell: u32) -> bool {
+    let mgr = cached_mgr(cell);
+    let ch = mgr.generate_challenge::<SpriteUri>().unwrap();
+
+    match ch.challenge_id.parse::<ParsedChallenge>() {
+        Ok(parsed) => parsed.timestamp == ch.timestamp && parsed.mac.len() == 32,
+        Err(_) => false,
+    }
+}
+
 fn prop_oob_rejected(idx: u8) -> bool {
     let mgr = build_mgr(60, 100, 20);
     let ch = mgr.generate_challenge::<SpriteUri>().unwrap();
@@ -77,6 +120,26 @@ fn prop_wrong_index_fails(cell: u32, q: u8, ttl: u64) -> bool {
         .unwrap_or(true)
 }
 
+#[cfg(feature = "test-utils")]
+fn prop_built_challenge_id_verifies(correct: u8) -> bool {
+    let mgr = build_mgr(60, 100, 20);
+    let (id, _) = geronimo_captcha::build_challenge_id(&[correct], b"s").unwrap();
+
+    mgr.verify_challenge(&id, correct).unwrap_or(false)
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn default_manager_roundtrip() {
+    let mgr = CaptchaManager::default();
+    let ch = mgr.generate_challenge::<SpriteUri>().unwrap();
+
+    assert!(
+        mgr.verify_challenge(&ch.challenge_id, ch.correct_number)
+            .unwrap()
+    );
+}
+
 proptest! {
     #![proptest_config(ProptestConfig {
         cases: 64,
@@ -118,4 +181,23 @@ proptest! {
     ) {
         prop_assert!(prop_wrong_index_fails(cell, q, ttl));
     }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn built_challenge_id_verifies(correct in 1u8..=9) {
+        prop_assert!(prop_built_challenge_id_verifies(correct));
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig {
+        cases: 1000,
+        .. ProptestConfig::default()
+    })]
+
+    // Small, cached cells keep a thousand renders quick
+    #[test]
+    fn generated_challenge_id_parses(cell in 32u32..=36) {
+        prop_assert!(prop_challenge_id_parses(cell));
+    }
 }
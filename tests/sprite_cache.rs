@@ -0,0 +1,41 @@
+#![cfg(feature = "test-utils")]
+
+use geronimo_captcha::image_utils::encode_image;
+use geronimo_captcha::{
+    GenerationOptions, NoiseOptions, SpriteBinary, SpriteCache, build_challenge_id,
+    create_sprite_seeded,
+};
+
+const BASE: &[u8] = include_bytes!("../assets/sample1.jpg");
+
+fn render(seed: u64, opts: &GenerationOptions) -> (SpriteBinary, u8) {
+    let (sprite, correct) =
+        create_sprite_seeded(BASE, opts, &NoiseOptions::default(), seed).expect("seeded sprite");
+    let (bytes, mime) = encode_image(&sprite, &opts.sprite_format).expect("encode sprite");
+
+    (SpriteBinary { bytes, mime }, correct)
+}
+
+#[test]
+fn seeded_challenge_replays_identical_sprite() {
+    let opts = GenerationOptions {
+        cell_size: 64,
+        ..Default::default()
+    };
+    let cache = SpriteCache::new(4);
+
+    let mut ids = Vec::new();
+    for seed in 0..6 {
+        let (sprite, correct) = render(seed, &opts);
+        let (id, _) = build_challenge_id(&[correct], b"secret").unwrap();
+        assert!(cache.insert(id.clone(), sprite).is_none());
+        ids.push((id, seed));
+    }
+    assert_eq!(cache.len(), 4);
+    assert!(cache.get(&ids[0].0).is_none());
+
+    for (id, seed) in &ids[2..] {
+        let (replayed, _) = render(*seed, &opts);
+        assert_eq!(cache.get(id), Some(replayed));
+    }
+}
@@ -0,0 +1,35 @@
+use geronimo_captcha::{CaptchaManager, GenerationOptions, NoiseOptions, SpriteBinary};
+
+const CELL_SIZE: u32 = 120;
+const COLS: u32 = 3;
+const ROWS: u32 = 3;
+const SPACING: u32 = 4;
+
+#[test]
+fn sprite_has_expected_pixel_dimensions() {
+    let mgr = CaptchaManager::new(
+        "s".into(),
+        60,
+        NoiseOptions::default(),
+        None,
+        GenerationOptions {
+            cell_size: CELL_SIZE,
+            ..Default::default()
+        },
+    )
+    .expect("valid options");
+
+    let ch = mgr.generate_challenge::<SpriteBinary>().unwrap();
+
+    let path = std::env::temp_dir().join(format!(
+        "geronimo-captcha-dimensions-{}.jpg",
+        std::process::id()
+    ));
+    ch.save_sprite(&path).unwrap();
+    let dims = image::image_dimensions(&path);
+    std::fs::remove_file(&path).unwrap();
+
+    let (width, height) = dims.unwrap();
+    assert_eq!(width, COLS * CELL_SIZE + (COLS - 1) * SPACING);
+    assert_eq!(height, ROWS * CELL_SIZE + (ROWS - 1) * SPACING);
+}
@@ -0,0 +1,14 @@
+//! Compile-time checks on auto traits of the public types, a failing bound
+//! breaks the build of this test. `CaptchaManager` is checked next to its
+//! definition.
+
+use geronimo_captcha::{
+    CaptchaChallenge, CaptchaError, ChallengeInMemoryRegistry, ChallengeRegistry, SpriteUri,
+};
+use static_assertions::assert_impl_all;
+use std::sync::Arc;
+
+assert_impl_all!(CaptchaChallenge<SpriteUri>: Send);
+assert_impl_all!(ChallengeInMemoryRegistry: Send, Sync);
+assert_impl_all!(Arc<dyn ChallengeRegistry>: ChallengeRegistry, Clone, Send, Sync);
+assert_impl_all!(CaptchaError: Send, Sync, std::error::Error);